@@ -0,0 +1,71 @@
+use syn::{
+    visit_mut::{self, VisitMut},
+    Ident, PathSegment, Type,
+};
+
+/// Replaces each reference to one of the trait's associated types
+/// (`Self::Item`, `<Self as Foo>::Item`) with the type parameter that stands
+/// for it (`Item`), as `Self` is no longer the original type in the erased
+/// trait.
+pub(crate) struct LiftAssocTypes<'a> {
+    pub(crate) trait_ident: &'a Ident,
+    pub(crate) assoc_types: &'a [Ident],
+}
+
+impl LiftAssocTypes<'_> {
+    fn lifted(&self, ty: &Type) -> Option<Ident> {
+        let assoc = own_assoc(ty, self.trait_ident)?;
+        if !assoc.arguments.is_empty() {
+            return None;
+        }
+        self.assoc_types
+            .iter()
+            .find(|ident| **ident == assoc.ident)
+            .cloned()
+    }
+}
+
+impl VisitMut for LiftAssocTypes<'_> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        match self.lifted(ty) {
+            Some(ident) => *ty = syn::parse_quote!(#ident),
+            None => visit_mut::visit_type_mut(self, ty),
+        }
+    }
+}
+
+/// If `ty` is `Self::Item` or `<Self as Foo>::Item`, where `Foo` is
+/// `trait_ident`, returns the `Item` segment (with any generic arguments).
+/// `<Self as Bar>::Item` is a supertrait's, even if `Foo` has an `Item` too.
+pub(crate) fn own_assoc<'t>(ty: &'t Type, trait_ident: &Ident) -> Option<&'t PathSegment> {
+    let type_path = match ty {
+        Type::Path(type_path) => type_path,
+        _ => return None,
+    };
+    let segments = &type_path.path.segments;
+    match &type_path.qself {
+        // `Self::Item`
+        None if segments.len() == 2 && segments[0].ident == "Self" => Some(&segments[1]),
+        // `<Self as Foo>::Item`
+        Some(qself)
+            if is_self(&qself.ty)
+                && qself.position == 1
+                && segments.len() == 2
+                && segments[0].ident == *trait_ident =>
+        {
+            Some(&segments[1])
+        }
+        _ => None,
+    }
+}
+
+/// True if `ty` is `<Self as Bar>::Item`, for a trait `Bar` other than
+/// `trait_ident` (i.e., a supertrait).
+pub(crate) fn is_supertrait_assoc(ty: &Type, trait_ident: &Ident) -> bool {
+    matches!(ty, Type::Path(p) if p.qself.as_ref().is_some_and(|qself| is_self(&qself.ty)))
+        && own_assoc(ty, trait_ident).is_none()
+}
+
+fn is_self(ty: &Type) -> bool {
+    matches!(ty, Type::Path(p) if p.qself.is_none() && p.path.is_ident("Self"))
+}
@@ -0,0 +1,438 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    visit_mut::VisitMut, Ident, ItemTrait, TraitBound, TraitBoundModifier, TraitItem,
+    TraitItemType, TypeParamBound,
+};
+
+use crate::assoc_types::LiftAssocTypes;
+use crate::gat::{self, Gat};
+use crate::method::Method;
+use crate::supertraits::{DynerSupertrait, StdTrait, Supertrait};
+
+pub(crate) fn expand(attr: TokenStream, item_trait: ItemTrait) -> syn::Result<TokenStream> {
+    let std_traits = StdTrait::parse_args(attr)?;
+    let dyner_trait = DynerTrait::new(&item_trait, std_traits)?;
+    let erased_trait = dyner_trait.erased_trait();
+    let erased_impl = dyner_trait.erased_impl();
+    let dyn_struct = dyner_trait.dyn_struct();
+    let dyn_impl = dyner_trait.dyn_impl();
+    let dyn_constructors = dyner_trait.dyn_constructors();
+    let supertrait_impls = dyner_trait.supertrait_impls();
+    let forward_macro = dyner_trait.forward_macro();
+
+    let mut item_trait = item_trait.clone();
+    gat::strip_dyner_attrs(&mut item_trait);
+
+    Ok(quote! {
+        #item_trait
+        #erased_trait
+        #erased_impl
+        #dyn_struct
+        #dyn_impl
+        #dyn_constructors
+        #supertrait_impls
+        #forward_macro
+    })
+}
+
+/// A trait annotated with `#[dyner]`. Given `trait Foo`, we generate:
+///
+/// * `trait ErasedFoo`, an object-safe version of `Foo`, along with a
+///   blanket impl of `ErasedFoo` for `Remember<P>` whenever `P::Target: Foo`.
+///   Each method `foo` is named `erased_foo` in `ErasedFoo`, as otherwise
+///   calling `foo` would be ambiguous wherever `ErasedFoo` is in scope.
+/// * `struct DynFoo<'data>`, which stores a `DynPtr<dyn ErasedFoo>` and
+///   implements `Foo` by dispatching through it.
+///
+/// Each associated type of `Foo` (e.g., `type Item`) becomes a type
+/// parameter of both `ErasedFoo<Item>` and `DynFoo<'data, Item>`, which can
+/// be constructed from any `T: Foo<Item = Item>`. Generic associated types
+/// (e.g., `type View<'a>`) are erased instead (see [`Gat`]).
+///
+/// If `Foo: Bar` for another `#[dyner]` trait `Bar`, then
+/// `ErasedFoo: ErasedBar`, and `DynFoo` implements `Bar` too, as well as
+/// upcasting to `DynBar` (see [`Supertrait`]). `Bar`'s expansion provides
+/// the impl, through a macro it exports under the name `DynBar`.
+///
+/// `Debug`, `Display` and `Clone`, whether supertraits or requested with
+/// `#[dyner(Debug, ..)]`, get an extra method in `ErasedFoo` each, which
+/// `DynFoo`'s impl of the trait dispatches through (see [`StdTrait`]).
+struct DynerTrait<'t> {
+    item_trait: &'t ItemTrait,
+    erased_ident: Ident,
+    dyn_ident: Ident,
+    assoc_types: Vec<&'t TraitItemType>,
+    /// The names of `assoc_types`.
+    assoc_idents: Vec<Ident>,
+    gats: Vec<Gat>,
+    methods: Vec<Method<'t>>,
+    dyner_supertraits: Vec<DynerSupertrait>,
+    std_traits: Vec<StdTrait>,
+}
+
+impl<'t> DynerTrait<'t> {
+    fn new(item_trait: &'t ItemTrait, mut std_traits: Vec<StdTrait>) -> syn::Result<Self> {
+        if !item_trait.generics.params.is_empty() || item_trait.generics.where_clause.is_some() {
+            return Err(syn::Error::new_spanned(
+                &item_trait.generics,
+                "`#[dyner]` does not support generic traits",
+            ));
+        }
+
+        if let Some(unsafety) = &item_trait.unsafety {
+            return Err(syn::Error::new_spanned(
+                unsafety,
+                "`#[dyner]` does not support unsafe traits",
+            ));
+        }
+
+        // Plain associated types first, as the bounds of generic ones may
+        // refer to them.
+        let mut assoc_types = vec![];
+        let mut generic_assoc_types = vec![];
+        let mut sigs = vec![];
+        for item in &item_trait.items {
+            match item {
+                TraitItem::Fn(item_fn) => sigs.push(&item_fn.sig),
+                TraitItem::Type(item_type) if item_type.generics.params.is_empty() => {
+                    if let Some(where_clause) = &item_type.generics.where_clause {
+                        return Err(syn::Error::new_spanned(
+                            where_clause,
+                            "`#[dyner]` does not support where clauses on associated types",
+                        ));
+                    }
+                    assoc_types.push(item_type);
+                }
+                TraitItem::Type(item_type) => generic_assoc_types.push(item_type),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        item,
+                        "`#[dyner]` traits may only contain methods and associated types",
+                    ))
+                }
+            }
+        }
+        let assoc_idents: Vec<Ident> = assoc_types.iter().map(|t| t.ident.clone()).collect();
+        let gats = generic_assoc_types
+            .into_iter()
+            .map(|item_type| Gat::new(item_type, &item_trait.ident, &assoc_idents))
+            .collect::<syn::Result<Vec<_>>>()?;
+        let methods = sigs
+            .into_iter()
+            .map(|sig| Method::new(sig, &item_trait.ident, &gats))
+            .collect::<syn::Result<Vec<_>>>()?;
+
+        let mut dyner_supertraits = vec![];
+        for bound in &item_trait.supertraits {
+            match Supertrait::new(bound)? {
+                Supertrait::Dyner(supertrait) => dyner_supertraits.push(supertrait),
+                Supertrait::Std(std_trait) if !std_traits.contains(&std_trait) => {
+                    std_traits.push(std_trait)
+                }
+                Supertrait::Std(_) => {}
+            }
+        }
+
+        Ok(DynerTrait {
+            item_trait,
+            erased_ident: format_ident!("Erased{}", item_trait.ident),
+            dyn_ident: format_ident!("Dyn{}", item_trait.ident),
+            assoc_types,
+            assoc_idents,
+            gats,
+            methods,
+            dyner_supertraits,
+            std_traits,
+        })
+    }
+
+    /// `<Item, ..>`, or nothing if there are no associated types.
+    fn erased_generics(&self) -> TokenStream {
+        let assoc_idents = &self.assoc_idents;
+        if assoc_idents.is_empty() {
+            quote!()
+        } else {
+            quote!(<#(#assoc_idents),*>)
+        }
+    }
+
+    /// The associated types as type parameters, with their bounds (e.g.,
+    /// `Item: Debug`), to follow `'data` in an `impl` header. If `DynFoo` is
+    /// `Clone`, they must also outlive `'data`, as `erased_clone` requires
+    /// `dyn ErasedFoo<Item> + 'data: 'data`.
+    fn bounded_params(&self) -> Vec<TokenStream> {
+        let outlives = self
+            .std_traits
+            .contains(&StdTrait::Clone)
+            .then(|| quote!('data));
+        self.assoc_types
+            .iter()
+            .map(|item_type| {
+                let ident = &item_type.ident;
+                // The type parameters are always `Sized`, so drop `?Sized`.
+                let bounds = item_type.bounds.iter().filter(|bound| {
+                    !matches!(
+                        bound,
+                        TypeParamBound::Trait(TraitBound {
+                            modifier: TraitBoundModifier::Maybe(_),
+                            ..
+                        })
+                    )
+                });
+                let bounds = bounds.cloned().map(|mut bound| {
+                    LiftAssocTypes {
+                        trait_ident: &self.item_trait.ident,
+                        assoc_types: &self.assoc_idents,
+                    }
+                    .visit_type_param_bound_mut(&mut bound);
+                    quote!(#bound)
+                });
+                let bounds = outlives.iter().cloned().chain(bounds);
+                quote!(#ident: #(#bounds)+*)
+            })
+            .collect()
+    }
+
+    /// `<'data, Item: Debug, ..>`, for implementing traits for `DynFoo`.
+    fn impl_generics(&self) -> TokenStream {
+        let bounded_params = self.bounded_params();
+        quote!(<'data #(, #bounded_params)*>)
+    }
+
+    /// `DynFoo<'data, Item, ..>`.
+    fn dyn_ty(&self) -> TokenStream {
+        let dyn_ident = &self.dyn_ident;
+        let assoc_idents = &self.assoc_idents;
+        quote!(#dyn_ident<'data #(, #assoc_idents)*>)
+    }
+
+    /// `ErasedFoo<Item, ..>`.
+    fn erased_ty(&self) -> TokenStream {
+        let erased_ident = &self.erased_ident;
+        let erased_generics = self.erased_generics();
+        quote!(#erased_ident #erased_generics)
+    }
+
+    fn erased_trait(&self) -> TokenStream {
+        let vis = &self.item_trait.vis;
+        let erased_ident = &self.erased_ident;
+        let erased_generics = self.erased_generics();
+        let erased_ty = self.erased_ty();
+        let erased_supertraits = self.dyner_supertraits.iter().map(|s| s.erased_bound());
+        let decls = self
+            .methods
+            .iter()
+            .map(|m| m.erased_decl(&self.item_trait.ident, &self.assoc_idents));
+        let std_decls = self.std_traits.iter().map(|s| s.erased_decl(&erased_ty));
+        quote! {
+            #[doc(hidden)]
+            #vis trait #erased_ident #erased_generics:
+                ::dyner::dyn_ptr::ErasedDrop #(+ #erased_supertraits)*
+            {
+                #(#decls)*
+                #(#std_decls)*
+            }
+        }
+    }
+
+    fn erased_impl(&self) -> TokenStream {
+        let trait_ident = &self.item_trait.ident;
+        let erased_ident = &self.erased_ident;
+        let assoc_idents = &self.assoc_idents;
+        let erased_generics = self.erased_generics();
+        let erased_ty = self.erased_ty();
+        let ptr_bound = self.ptr_bound();
+        let target_bounds = self.target_bounds();
+        let methods = self
+            .methods
+            .iter()
+            .map(|m| m.erased_impl(trait_ident, assoc_idents));
+        let std_methods = self.std_traits.iter().map(|s| s.erased_impl(&erased_ty));
+        quote! {
+            impl<P #(, #assoc_idents)*> #erased_ident #erased_generics for ::dyner::dyn_ptr::Remember<P>
+            where
+                P: #ptr_bound,
+                P::Target: #target_bounds,
+            {
+                #(#methods)*
+                #(#std_methods)*
+            }
+        }
+    }
+
+    /// The bound on the pointer a `DynFoo` is constructed from: it has to be
+    /// `RawClone` if `DynFoo` is `Clone`, for cloning a `Ref<DynFoo>`.
+    fn ptr_bound(&self) -> TokenStream {
+        if self.std_traits.contains(&StdTrait::Clone) {
+            quote!(::dyner::dyn_ptr::RawClone)
+        } else {
+            quote!(::dyner::dyn_ptr::RawDeref)
+        }
+    }
+
+    /// Bounds on the target of the pointer a `DynFoo` is constructed from.
+    /// Targets may be unsized (e.g., `[T]`), unless there's a `self` method.
+    /// We can't tell whether a `#[dyner]` supertrait has one, so we require
+    /// `Sized` if there are any.
+    fn target_bounds(&self) -> TokenStream {
+        let trait_ident = &self.item_trait.ident;
+        let assoc_idents = &self.assoc_idents;
+        let trait_bound = if assoc_idents.is_empty() {
+            quote!(#trait_ident)
+        } else {
+            quote!(#trait_ident<#(#assoc_idents = #assoc_idents),*>)
+        };
+        let std_paths = self.std_traits.iter().map(|s| s.path());
+        let trait_bound = quote!(#trait_bound #(+ #std_paths)*);
+        if self.methods.iter().any(|m| m.takes_self_by_value())
+            || !self.dyner_supertraits.is_empty()
+        {
+            quote!(#trait_bound + ::core::marker::Sized)
+        } else {
+            trait_bound
+        }
+    }
+
+    fn dyn_struct(&self) -> TokenStream {
+        let vis = &self.item_trait.vis;
+        let trait_ident = &self.item_trait.ident;
+        let erased_ident = &self.erased_ident;
+        let erased_generics = self.erased_generics();
+        let assoc_idents = &self.assoc_idents;
+        let dyn_ident = &self.dyn_ident;
+        let doc = format!(
+            "A `{trait_ident}` object that uses dynamic dispatch; see \
+             [`{dyn_ident}::new`] and [`{dyn_ident}::from_ptr`]."
+        );
+        quote! {
+            #[doc = #doc]
+            #vis struct #dyn_ident<'data #(, #assoc_idents)*> {
+                ptr: ::dyner::dyn_ptr::DynPtr<dyn #erased_ident #erased_generics + 'data>,
+            }
+        }
+    }
+
+    fn dyn_impl(&self) -> TokenStream {
+        let trait_ident = &self.item_trait.ident;
+        let erased_ident = &self.erased_ident;
+        let assoc_idents = &self.assoc_idents;
+        let impl_generics = self.impl_generics();
+        let dyn_ty = self.dyn_ty();
+        let gats = self.gats.iter().map(|gat| gat.dyn_impl());
+        let methods = self.methods.iter().map(|m| m.dyn_impl(Some(erased_ident)));
+        quote! {
+            impl #impl_generics #trait_ident for #dyn_ty {
+                #(type #assoc_idents = #assoc_idents;)*
+                #(#gats)*
+                #(#methods)*
+            }
+        }
+    }
+
+    fn dyn_constructors(&self) -> TokenStream {
+        let target_bounds = self.target_bounds();
+        let erased_ident = &self.erased_ident;
+        let erased_generics = self.erased_generics();
+        let dyn_ident = &self.dyn_ident;
+        let assoc_idents = &self.assoc_idents;
+        let ptr_bound = self.ptr_bound();
+        let upcasts = self.dyner_supertraits.iter().map(|s| s.upcasts());
+        quote! {
+            impl<'data #(, #assoc_idents)*> #dyn_ident<'data #(, #assoc_idents)*> {
+                ::dyner::__if_alloc! {
+                    /// Constructs an owned object, by boxing `value`.
+                    pub fn new<T>(value: T) -> Self
+                    where
+                        T: #target_bounds + 'data,
+                    {
+                        #dyn_ident::from_ptr(::dyner::__private::Box::new(value))
+                    }
+                }
+
+                /// Constructs from any `RawDeref` pointer: a `Box<T>` gives an
+                /// owned object, `&mut T` a `RefMut`, and `Rc<T>` or `&T` a `Ref`.
+                pub fn from_ptr<P>(value: P) -> <P as ::dyner::dyn_ptr::RawDeref>::Wrapped<Self>
+                where
+                    P: #ptr_bound + 'data,
+                    P::Target: #target_bounds,
+                {
+                    // Okay because `Wrapped` guards mutable access.
+                    let ptr = unsafe {
+                        ::dyner::dyn_ptr::DynPtr::new(::dyner::dyn_ptr::Remember::new(value))
+                    };
+                    <P as ::dyner::dyn_ptr::RawDeref>::wrap(#dyn_ident { ptr })
+                }
+
+                /// For upcasting from the `Dyn` type of a subtrait.
+                ///
+                /// Unsafe: as for `DynPtr::new`, `ptr` must allow `&mut` access
+                /// to its target, or the result must only be given out in a `Ref`.
+                #[doc(hidden)]
+                pub unsafe fn __from_dyn_ptr(
+                    ptr: ::dyner::dyn_ptr::DynPtr<dyn #erased_ident #erased_generics + 'data>,
+                ) -> Self {
+                    #dyn_ident { ptr }
+                }
+
+                #(#upcasts)*
+            }
+        }
+    }
+
+    /// `impl Bar for DynFoo<'_>` for each supertrait `Bar`.
+    fn supertrait_impls(&self) -> TokenStream {
+        let impl_generics = self.impl_generics();
+        let dyn_ty = self.dyn_ty();
+        let dyner_impls = self
+            .dyner_supertraits
+            .iter()
+            .map(|s| s.forward(&impl_generics, &dyn_ty));
+        let std_impls = self
+            .std_traits
+            .iter()
+            .map(|s| s.dyn_impl(&self.erased_ident, &impl_generics, &dyn_ty));
+        quote! {
+            #(#dyner_impls)*
+            #(#std_impls)*
+        }
+    }
+
+    /// A macro that implements `Foo` for the `Dyn` type of a subtrait,
+    /// given the impl header. It's exported under the name `DynFoo`, which
+    /// is free in the macro namespace, so that a subtrait can find it by the
+    /// same path as `Foo`, as we do for `DynFoo` itself.
+    fn forward_macro(&self) -> TokenStream {
+        let macro_ident = format_ident!("__dyner_forward_{}", self.item_trait.ident);
+        let dyn_ident = &self.dyn_ident;
+        // Those would be type parameters of `DynBar`, which we can't tell
+        // the subtrait's expansion about.
+        let body = if self.assoc_types.is_empty() {
+            let gats = self.gats.iter().map(|gat| gat.dyn_impl());
+            let methods = self.methods.iter().map(|m| m.dyn_impl(None));
+            quote! {
+                $($header)* {
+                    #(#gats)*
+                    #(#methods)*
+                }
+            }
+        } else {
+            quote! {
+                ::core::compile_error!(
+                    "`#[dyner]` does not support supertraits with associated types"
+                );
+            }
+        };
+        quote! {
+            #[doc(hidden)]
+            #[allow(unused_macros)]
+            macro_rules! #macro_ident {
+                ($($header:tt)*) => { #body };
+            }
+
+            #[doc(hidden)]
+            #[allow(unused_imports)]
+            pub(crate) use #macro_ident as #dyn_ident;
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    visit::{self, Visit},
+    visit_mut::{self, VisitMut},
+    GenericArgument, GenericParam, Ident, Lifetime, Path, PathArguments, ReturnType, Signature,
+    TraitBoundModifier, TraitItemType, Type, TypeParamBound, WhereClause,
+};
+
+use crate::assoc_types::{own_assoc, LiftAssocTypes};
+use crate::method::future_output;
+
+/// A generic associated type with a single lifetime parameter, e.g.
+/// `type View<'a>: Display + 'a where Self: 'a`. `DynFoo` can't name the
+/// type the underlying object uses, so it returns its values erased:
+///
+/// * boxed, as `Box<dyn Display + 'a>`;
+/// * pinned and boxed if it's a future, as
+///   `Pin<Box<dyn Future<Output = T> + 'a>>`, so that it's still a future;
+/// * if the associated type is marked `#[dyner]`, as `DynBar<'a>`, where its
+///   bound `Bar` is itself a `#[dyner]` trait.
+#[derive(Clone)]
+pub(crate) struct Gat {
+    /// The trait it's declared in, for telling it apart from a
+    /// supertrait's of the same name.
+    trait_ident: Ident,
+    ident: Ident,
+    lifetime: Lifetime,
+    /// The trait bounds, without `'a`, which the erased type always has.
+    bounds: Vec<TypeParamBound>,
+    where_clause: Option<WhereClause>,
+    erasure: Erasure,
+}
+
+#[derive(Clone)]
+enum Erasure {
+    Boxed,
+    Pinned,
+    /// The path to `DynBar`.
+    Dyn(Path),
+}
+
+impl Gat {
+    pub(crate) fn new(
+        item_type: &TraitItemType,
+        trait_ident: &Ident,
+        assoc_types: &[Ident],
+    ) -> syn::Result<Self> {
+        let generics = &item_type.generics;
+        let lifetime = match (generics.params.first(), generics.params.len()) {
+            (Some(GenericParam::Lifetime(param)), 1) => param.lifetime.clone(),
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    generics,
+                    "`#[dyner]` only supports generic associated types with a single lifetime \
+                     parameter",
+                ))
+            }
+        };
+
+        let mut has_lifetime_bound = false;
+        let mut bounds = vec![];
+        for bound in &item_type.bounds {
+            match bound {
+                TypeParamBound::Lifetime(lt) if *lt == lifetime => has_lifetime_bound = true,
+                // The erased type is always `Sized`.
+                TypeParamBound::Trait(trait_bound)
+                    if matches!(trait_bound.modifier, TraitBoundModifier::Maybe(_)) => {}
+                _ => {
+                    let mut bound = bound.clone();
+                    LiftAssocTypes {
+                        trait_ident,
+                        assoc_types,
+                    }
+                    .visit_type_param_bound_mut(&mut bound);
+                    bounds.push(bound);
+                }
+            }
+        }
+        // Otherwise, the value might not live long enough to be erased.
+        if !has_lifetime_bound {
+            return Err(syn::Error::new_spanned(
+                item_type,
+                format!(
+                    "`#[dyner]` requires generic associated types to be bounded by their \
+                     lifetime (`+ {lifetime}`)"
+                ),
+            ));
+        }
+
+        let trait_paths: Vec<&Path> = bounds
+            .iter()
+            .filter_map(|bound| match bound {
+                TypeParamBound::Trait(trait_bound) => Some(&trait_bound.path),
+                _ => None,
+            })
+            .collect();
+        if trait_paths.is_empty() {
+            return Err(syn::Error::new_spanned(
+                item_type,
+                "`#[dyner]` requires generic associated types to have a trait bound",
+            ));
+        }
+
+        let erasure = if item_type.attrs.iter().any(is_dyner_attr) {
+            let trait_path = match trait_paths[..] {
+                [trait_path] if trait_path.segments.last().unwrap().arguments.is_empty() => {
+                    trait_path
+                }
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        item_type,
+                        "`#[dyner]` associated types must be bounded by a single `#[dyner]` \
+                         trait, without generic arguments",
+                    ))
+                }
+            };
+            let mut dyn_path = trait_path.clone();
+            let last = dyn_path.segments.last_mut().unwrap();
+            last.ident = format_ident!("Dyn{}", last.ident);
+            Erasure::Dyn(dyn_path)
+        } else if trait_paths.iter().any(|path| future_output(path).is_some()) {
+            Erasure::Pinned
+        } else {
+            Erasure::Boxed
+        };
+
+        Ok(Gat {
+            trait_ident: trait_ident.clone(),
+            ident: item_type.ident.clone(),
+            lifetime,
+            bounds,
+            where_clause: generics.where_clause.clone(),
+            erasure,
+        })
+    }
+
+    /// The erased type of `Self::View<'x>`, where `'x` is `lifetime`.
+    fn erased_ty(&self, lifetime: &Lifetime) -> Type {
+        let mut bounds = self.bounds.clone();
+        let mut rename = RenameLifetime {
+            from: &self.lifetime,
+            to: lifetime,
+        };
+        for bound in &mut bounds {
+            rename.visit_type_param_bound_mut(bound);
+        }
+        match &self.erasure {
+            Erasure::Boxed => syn::parse_quote! {
+                ::dyner::__private::Box<dyn #(#bounds)+* + #lifetime>
+            },
+            Erasure::Pinned => syn::parse_quote! {
+                ::core::pin::Pin<::dyner::__private::Box<dyn #(#bounds)+* + #lifetime>>
+            },
+            Erasure::Dyn(dyn_path) => syn::parse_quote!(#dyn_path<#lifetime>),
+        }
+    }
+
+    /// Erases `value`, of type `Self::View<'x>` for the underlying object, to
+    /// `erased_ty(lifetime)`.
+    fn erase(&self, value: TokenStream, lifetime: &Lifetime) -> TokenStream {
+        let erased_ty = self.erased_ty(lifetime);
+        match &self.erasure {
+            Erasure::Boxed => quote!(::dyner::__private::Box::new(#value) as #erased_ty),
+            Erasure::Pinned => quote!(::dyner::__private::Box::pin(#value) as #erased_ty),
+            Erasure::Dyn(dyn_path) => quote!(#dyn_path::new(#value)),
+        }
+    }
+
+    /// The definition of this type in `impl Foo for DynFoo<'_>`.
+    pub(crate) fn dyn_impl(&self) -> TokenStream {
+        let ident = &self.ident;
+        let lifetime = &self.lifetime;
+        let erased_ty = self.erased_ty(lifetime);
+        let where_clause = &self.where_clause;
+        quote!(type #ident<#lifetime> = #erased_ty #where_clause;)
+    }
+
+    /// If `ty` is `Self::View<'x>` (or `<Self as Foo>::View<'x>`), returns
+    /// `View` and `'x`.
+    fn find<'g>(gats: &'g [Gat], ty: &Type) -> Option<(&'g Gat, Lifetime)> {
+        let (gat, segment) = gats.iter().find_map(|gat| {
+            let segment = own_assoc(ty, &gat.trait_ident)?;
+            (segment.ident == gat.ident).then_some((gat, segment))
+        })?;
+        match &segment.arguments {
+            PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+                GenericArgument::Lifetime(lifetime) => Some((gat, lifetime.clone())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+fn is_dyner_attr(attr: &syn::Attribute) -> bool {
+    attr.path().is_ident("dyner")
+}
+
+/// Removes the `#[dyner]` markers from the associated types of `item_trait`,
+/// as there's no such attribute once we've expanded.
+pub(crate) fn strip_dyner_attrs(item_trait: &mut syn::ItemTrait) {
+    for item in &mut item_trait.items {
+        if let syn::TraitItem::Type(item_type) = item {
+            item_type.attrs.retain(|attr| !is_dyner_attr(attr));
+        }
+    }
+}
+
+/// A method that returns a generic associated type, either directly
+/// (`Self::View<'_>`) or in an `Option` (as lending iterators do).
+pub(crate) struct GatOutput {
+    gat: Gat,
+    lifetime: Lifetime,
+    optional: bool,
+}
+
+impl GatOutput {
+    pub(crate) fn from_sig(sig: &Signature, gats: &[Gat]) -> syn::Result<Option<Self>> {
+        let mut output = None;
+        if let (None, ReturnType::Type(_, ty)) = (&sig.asyncness, &sig.output) {
+            if let Some((gat, lifetime)) = Gat::find(gats, ty) {
+                output = Some((gat, lifetime, false));
+            } else if let Some(ty) = option_arg(ty) {
+                output = Gat::find(gats, ty).map(|(gat, lifetime)| (gat, lifetime, true));
+            }
+        }
+
+        // Anywhere else, we'd have no way to convert the values.
+        let mut find = FindGat { gats, found: None };
+        for input in &sig.inputs {
+            find.visit_fn_arg(input);
+        }
+        if output.is_none() {
+            find.visit_return_type(&sig.output);
+        }
+        if let Some(ty) = find.found {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "`#[dyner]` only supports generic associated types as the return type of a \
+                 non-async method, or in an `Option` there",
+            ));
+        }
+
+        Ok(output.map(|(gat, lifetime, optional)| GatOutput {
+            gat: gat.clone(),
+            lifetime,
+            optional,
+        }))
+    }
+
+    /// The return type of the method in the erased trait.
+    pub(crate) fn erased_ty(&self) -> Type {
+        let ty = self.gat.erased_ty(&self.lifetime);
+        if self.optional {
+            syn::parse_quote!(::core::option::Option<#ty>)
+        } else {
+            ty
+        }
+    }
+
+    /// Erases `call`, the result of calling the method on the underlying
+    /// object.
+    pub(crate) fn erase(&self, call: TokenStream) -> TokenStream {
+        if self.optional {
+            let erased = self.gat.erase(quote!(__value), &self.lifetime);
+            quote!(::core::option::Option::map(#call, |__value| #erased))
+        } else {
+            self.gat.erase(call, &self.lifetime)
+        }
+    }
+}
+
+/// If `ty` is `Option<T>`, returns `T`.
+fn option_arg(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(type_path) if type_path.qself.is_none() => type_path.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+struct FindGat<'g, 'ast> {
+    gats: &'g [Gat],
+    found: Option<&'ast Type>,
+}
+
+impl<'ast> Visit<'ast> for FindGat<'_, 'ast> {
+    fn visit_type(&mut self, ty: &'ast Type) {
+        if Gat::find(self.gats, ty).is_some() {
+            self.found.get_or_insert(ty);
+        }
+        visit::visit_type(self, ty);
+    }
+}
+
+/// Renames the lifetime `from` to `to`.
+struct RenameLifetime<'a> {
+    from: &'a Lifetime,
+    to: &'a Lifetime,
+}
+
+impl VisitMut for RenameLifetime<'_> {
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if lifetime == self.from {
+            *lifetime = self.to.clone();
+        }
+        visit_mut::visit_lifetime_mut(self, lifetime);
+    }
+}
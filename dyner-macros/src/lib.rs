@@ -0,0 +1,69 @@
+//! The `#[dyner]` attribute macro. See the `dyner` crate for the runtime
+//! types that the generated code builds on.
+
+use proc_macro::TokenStream;
+
+mod assoc_types;
+mod expand;
+mod gat;
+mod lifetimes;
+mod method;
+mod supertraits;
+
+/// Given a trait `Foo`, generates a `DynFoo<'data>` type that implements
+/// `Foo` via dynamic dispatch. `DynFoo` can be constructed with
+/// `DynFoo::new` (owned), or with `DynFoo::from_ptr` from any
+/// `dyner::dyn_ptr::RawDeref` pointer (`Box`, `Rc`, `&T`, `&mut T`).
+///
+/// `async fn` methods (and methods returning `impl Future`) are supported;
+/// `DynFoo` returns their futures boxed.
+///
+/// Without `dyner`'s `alloc` feature, `DynFoo::new` isn't generated, and
+/// `async fn` methods aren't supported; `from_ptr` still works with `&T` and
+/// `&mut T`.
+///
+/// Arguments of type `impl Bar`, `&impl Bar` or `&mut impl Bar` are
+/// supported if `Bar` is also a `#[dyner]` trait: they are converted to
+/// `DynBar` before being passed through the vtable. So are generic methods
+/// such as `fn visit<V: Bar>(&self, v: &mut V)`, whose type parameters may
+/// only be used in that way.
+///
+/// Associated types become type parameters of `DynFoo`: given
+/// `trait Foo { type Item; }`, `DynFoo<'data, Item>` implements
+/// `Foo<Item = Item>` and can be constructed from any `T: Foo<Item = Item>`.
+///
+/// Generic associated types with a single lifetime, e.g.
+/// `type View<'a>: Display + 'a where Self: 'a`, can be returned by methods
+/// (directly, or in an `Option`): `DynFoo` returns them as
+/// `Box<dyn Display + 'a>`, or, for a future, as a pinned box. Mark the
+/// associated type `#[dyner]` if its bound is itself a `#[dyner]` trait
+/// `Bar`, to return a `DynBar<'a>` instead.
+///
+/// Supertraits that are themselves `#[dyner]` traits are supported: given
+/// `trait Foo: Bar`, `DynFoo` implements `Bar` and can be upcast with
+/// `as_bar` (giving a `Ref<DynBar>`) or `into_bar` (giving a `DynBar` that
+/// owns the object if the `DynFoo` did). `DynBar` must be nameable by the
+/// same path as `Bar`, `Bar` must not have (non-generic) associated types,
+/// and the types in its signatures must also be nameable where `Foo` is
+/// defined. `Foo`'s methods can't name `Bar`'s generic associated types
+/// (`<Self as Bar>::View<'_>`), even if `Foo` has one of the same name.
+/// Supertraits of `Bar` aren't inherited, so list them on `Foo` too.
+///
+/// `Debug`, `Display` and `Clone` can be forwarded to the underlying object,
+/// either by making them supertraits of `Foo` or with `#[dyner(Debug, ..)]`,
+/// which only requires them of the types `DynFoo` is constructed from.
+/// Cloning a `DynFoo` clones the object into a new `Box` (so it needs
+/// `alloc`), while cloning a `Ref<DynFoo>` copies the reference (or `Rc`)
+/// it was constructed from. With `Clone`, associated types must outlive
+/// `'data`.
+///
+/// By-value `self` methods can be called on an owned `DynFoo`, which moves
+/// the object out of its box; `Ref<DynFoo>` and `RefMut<DynFoo>` don't let
+/// you move out, so they can't be consumed this way.
+#[proc_macro_attribute]
+pub fn dyner(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item_trait = syn::parse_macro_input!(item as syn::ItemTrait);
+    expand::expand(attr.into(), item_trait)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,56 @@
+use proc_macro2::Span;
+use syn::{
+    visit_mut::{self, VisitMut},
+    Lifetime, ParenthesizedGenericArguments, Receiver, TypeBareFn, TypeReference,
+};
+
+/// Replaces each elided lifetime in a method's inputs (`&T`, `'_`) with a
+/// fresh named lifetime, so that we can relate it to the lifetime of the
+/// boxed future we return.
+#[derive(Default)]
+pub(crate) struct NameElidedLifetimes {
+    /// The lifetimes we have introduced, in order.
+    pub(crate) lifetimes: Vec<Lifetime>,
+}
+
+impl NameElidedLifetimes {
+    fn fresh(&mut self) -> Lifetime {
+        let lifetime = Lifetime::new(&format!("'life{}", self.lifetimes.len()), Span::call_site());
+        self.lifetimes.push(lifetime.clone());
+        lifetime
+    }
+}
+
+impl VisitMut for NameElidedLifetimes {
+    fn visit_receiver_mut(&mut self, receiver: &mut Receiver) {
+        if let Some((_, lifetime @ None)) = &mut receiver.reference {
+            *lifetime = Some(self.fresh());
+        }
+
+        // `receiver.ty` is the desugared type of `self`, which has to agree.
+        if let Some((_, lifetime)) = &receiver.reference {
+            let mutability = receiver.mutability;
+            *receiver.ty = syn::parse_quote!(&#lifetime #mutability Self);
+        }
+    }
+
+    fn visit_type_reference_mut(&mut self, ty: &mut TypeReference) {
+        if ty.lifetime.is_none() {
+            ty.lifetime = Some(self.fresh());
+        }
+        visit_mut::visit_type_reference_mut(self, ty);
+    }
+
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if lifetime.ident == "_" {
+            *lifetime = self.fresh();
+        }
+    }
+
+    // `fn(&T)` and `Fn(&T)` introduce their own elision scopes.
+
+    fn visit_type_bare_fn_mut(&mut self, _: &mut TypeBareFn) {}
+
+    fn visit_parenthesized_generic_arguments_mut(&mut self, _: &mut ParenthesizedGenericArguments) {
+    }
+}
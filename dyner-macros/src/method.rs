@@ -0,0 +1,663 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    visit::{self, Visit},
+    visit_mut::VisitMut,
+    FnArg, GenericArgument, GenericParam, Ident, Lifetime, Path, PathArguments, ReturnType,
+    Signature, TraitBoundModifier, Type, TypeImplTrait, TypeParamBound, WherePredicate,
+};
+
+use crate::assoc_types::{is_supertrait_assoc, LiftAssocTypes};
+use crate::gat::{Gat, GatOutput};
+use crate::lifetimes::NameElidedLifetimes;
+
+/// A method of a `#[dyner]` trait.
+pub(crate) struct Method<'t> {
+    sig: &'t Signature,
+    self_kind: SelfKind,
+    args: Vec<Arg<'t>>,
+
+    /// If this is an `async fn` (or returns `impl Future`), the future's
+    /// output; the erased trait returns a boxed future instead.
+    future: Option<FutureOutput>,
+
+    /// If this returns a generic associated type (e.g., `Self::View<'_>`),
+    /// which the erased trait returns erased.
+    gat_output: Option<GatOutput>,
+}
+
+enum SelfKind {
+    /// `&self`
+    Ref,
+    /// `&mut self`
+    Mut,
+    /// `self`, which the erased trait takes as `unsafe fn(&mut self)`, moving
+    /// the object out of its `Box`. Only an owned `DynFoo` (from
+    /// `DynFoo::new`, or `DynFoo::from_ptr` with a `Box`) can be consumed like
+    /// this, as `Ref` and `RefMut` don't let you move out.
+    Value,
+}
+
+struct Arg<'t> {
+    ident: Ident,
+    ty: &'t Type,
+
+    /// If the argument is `impl Foo` (or `&impl Foo`, `&mut impl Foo`), or
+    /// of a type parameter `V: Foo` (or `&V`, `&mut V`), the erased trait
+    /// takes a `DynFoo` instead.
+    dyn_arg: Option<DynArg>,
+}
+
+/// A type parameter of a method, e.g. `V: Visitor`, which we erase to
+/// `DynVisitor` just like `impl Visitor`.
+struct TypeParam<'t> {
+    ident: &'t Ident,
+    trait_path: &'t Path,
+}
+
+/// An `impl Foo` argument, where `Foo` is itself a `#[dyner]` trait.
+struct DynArg {
+    /// Path to `DynFoo`.
+    dyn_path: Path,
+    mode: DynArgMode,
+}
+
+enum DynArgMode {
+    /// `impl Foo` or `V`, converted with `DynFoo::new`.
+    Owned,
+    /// `&impl Foo` or `&V`, converted with `DynFoo::from_ptr`.
+    Ref(Option<Lifetime>),
+    /// `&mut impl Foo` or `&mut V`, converted with `DynFoo::from_ptr`.
+    Mut(Option<Lifetime>),
+}
+
+struct FutureOutput {
+    output: Type,
+
+    /// Extra bounds on the future (e.g., `Send`), which are carried over to
+    /// the boxed `dyn Future`.
+    bounds: Vec<TypeParamBound>,
+}
+
+impl<'t> Method<'t> {
+    pub(crate) fn new(sig: &'t Signature, trait_ident: &Ident, gats: &[Gat]) -> syn::Result<Self> {
+        let type_params = TypeParam::from_sig(sig)?;
+        no_supertrait_assoc_types(sig, trait_ident)?;
+
+        let self_kind = match sig.receiver() {
+            Some(receiver) if receiver.colon_token.is_none() => {
+                match (&receiver.reference, &receiver.mutability) {
+                    (Some(_), None) => SelfKind::Ref,
+                    (Some(_), Some(_)) => SelfKind::Mut,
+                    (None, _) => SelfKind::Value,
+                }
+            }
+            Some(receiver) => {
+                return Err(syn::Error::new_spanned(
+                    receiver,
+                    "`#[dyner]` methods must take `self`, `&self` or `&mut self`",
+                ))
+            }
+            None => {
+                return Err(syn::Error::new_spanned(
+                    sig,
+                    "`#[dyner]` methods must take `self`, `&self` or `&mut self`",
+                ))
+            }
+        };
+
+        let args = sig
+            .inputs
+            .iter()
+            .filter_map(|input| match input {
+                FnArg::Receiver(_) => None,
+                FnArg::Typed(pat_type) => Some(&*pat_type.ty),
+            })
+            .enumerate()
+            .map(|(i, ty)| {
+                Ok(Arg {
+                    ident: format_ident!("__arg{}", i),
+                    ty,
+                    dyn_arg: DynArg::from_type(ty, &type_params)?,
+                })
+            })
+            .collect::<syn::Result<_>>()?;
+        if let ReturnType::Type(_, ty) = &sig.output {
+            no_type_params(ty, &type_params)?;
+        }
+
+        let future = FutureOutput::from_sig(sig)?;
+        let gat_output = GatOutput::from_sig(sig, gats)?;
+
+        Ok(Method {
+            sig,
+            self_kind,
+            args,
+            future,
+            gat_output,
+        })
+    }
+
+    /// The signature used in the impl of the original trait for `DynFoo`:
+    /// the same as the original, but with each argument bound to a fresh
+    /// identifier so that we can forward it.
+    fn impl_sig(&self) -> Signature {
+        let mut sig = self.sig.clone();
+        let mut receiver = sig
+            .inputs
+            .iter()
+            .find(|i| matches!(i, FnArg::Receiver(_)))
+            .cloned();
+        if let Some(FnArg::Receiver(receiver)) = &mut receiver {
+            if let SelfKind::Value = self.self_kind {
+                // `mut self` only matters to the original method body.
+                receiver.mutability = None;
+            }
+        }
+        let args = self
+            .args
+            .iter()
+            .map(|Arg { ident, ty, .. }| -> FnArg { syn::parse_quote!(#ident: #ty) });
+        sig.inputs = receiver.into_iter().chain(args).collect();
+        sig
+    }
+
+    /// The signature used in the erased trait. This is the same as
+    /// [`Self::impl_sig`] except that the method is named `erased_foo`,
+    /// `self` becomes `unsafe fn(&mut self)`, `impl Foo` arguments (and
+    /// those of type `V: Foo`) become `DynFoo`, associated types (`Self::Item`)
+    /// become the erased trait's type parameters (`Item`), generic
+    /// associated types are erased (see [`Gat`]), and async methods are
+    /// desugared to return a boxed future:
+    ///
+    /// ```text
+    /// async fn fetch(&self, key: &str) -> Vec<u8>;
+    ///
+    /// // becomes
+    ///
+    /// fn erased_fetch<'life0, 'life1, 'dyner>(&'life0 self, key: &'life1 str)
+    ///     -> Pin<Box<dyn Future<Output = Vec<u8>> + 'dyner>>
+    /// where
+    ///     'life0: 'dyner,
+    ///     'life1: 'dyner;
+    /// ```
+    fn erased_sig(&self, trait_ident: &Ident, assoc_types: &[Ident]) -> Signature {
+        let mut sig = self.unlifted_erased_sig();
+        LiftAssocTypes {
+            trait_ident,
+            assoc_types,
+        }
+        .visit_signature_mut(&mut sig);
+        sig
+    }
+
+    fn unlifted_erased_sig(&self) -> Signature {
+        let mut sig = self.impl_sig();
+        sig.ident = self.erased_ident();
+        // Type parameters only appear in arguments that we erase below.
+        let type_params: Vec<Ident> = sig
+            .generics
+            .type_params()
+            .map(|param| param.ident.clone())
+            .collect();
+        sig.generics.params = sig
+            .generics
+            .params
+            .into_iter()
+            .filter(|param| matches!(param, GenericParam::Lifetime(_)))
+            .collect();
+        if let Some(where_clause) = &mut sig.generics.where_clause {
+            where_clause.predicates = std::mem::take(&mut where_clause.predicates)
+                .into_iter()
+                .filter(|predicate| match predicate {
+                    WherePredicate::Type(predicate) => !type_params
+                        .iter()
+                        .any(|ident| is_type_param(&predicate.bounded_ty, ident)),
+                    _ => true,
+                })
+                .collect();
+        }
+        if let SelfKind::Value = self.self_kind {
+            sig.inputs[0] = syn::parse_quote!(&mut self);
+            sig.unsafety = Some(Default::default());
+        }
+        for (input, arg) in sig.inputs.iter_mut().skip(1).zip(&self.args) {
+            if let (FnArg::Typed(pat_type), Some(dyn_arg)) = (input, &arg.dyn_arg) {
+                *pat_type.ty = dyn_arg.erased_ty();
+            }
+        }
+        if let Some(gat_output) = &self.gat_output {
+            let ty = gat_output.erased_ty();
+            sig.output = syn::parse_quote!(-> #ty);
+        }
+
+        let future = match &self.future {
+            Some(future) => future,
+            None => return sig,
+        };
+
+        sig.asyncness = None;
+
+        // For `self` methods, the object is moved out before the future is
+        // created, so the future doesn't borrow it.
+        let skip = match self.self_kind {
+            SelfKind::Value => 1,
+            SelfKind::Ref | SelfKind::Mut => 0,
+        };
+        let mut name_elided = NameElidedLifetimes::default();
+        for input in sig.inputs.iter_mut().skip(skip) {
+            name_elided.visit_fn_arg_mut(input);
+        }
+
+        let dyner_lt = Lifetime::new("'dyner", Span::call_site());
+        let lifetimes: Vec<Lifetime> = sig
+            .generics
+            .lifetimes()
+            .map(|param| param.lifetime.clone())
+            .chain(name_elided.lifetimes)
+            .collect();
+        sig.generics.params = lifetimes
+            .iter()
+            .map(|lt| -> GenericParam { syn::parse_quote!(#lt) })
+            .chain(Some(syn::parse_quote!(#dyner_lt)))
+            .collect();
+        let where_clause = sig.generics.make_where_clause();
+        for lt in &lifetimes {
+            where_clause
+                .predicates
+                .push(syn::parse_quote!(#lt: #dyner_lt));
+        }
+        if let SelfKind::Value = self.self_kind {
+            // The future owns `self`.
+            where_clause
+                .predicates
+                .push(syn::parse_quote!(Self: #dyner_lt));
+        }
+
+        let FutureOutput { output, bounds } = future;
+        sig.output = syn::parse_quote! {
+            -> ::core::pin::Pin<::dyner::__private::Box<
+                dyn ::core::future::Future<Output = #output> #(+ #bounds)* + #dyner_lt
+            >>
+        };
+        sig
+    }
+
+    pub(crate) fn takes_self_by_value(&self) -> bool {
+        matches!(self.self_kind, SelfKind::Value)
+    }
+
+    fn erased_ident(&self) -> Ident {
+        format_ident!("erased_{}", self.sig.ident)
+    }
+
+    /// Declaration of this method in the erased trait.
+    pub(crate) fn erased_decl(&self, trait_ident: &Ident, assoc_types: &[Ident]) -> TokenStream {
+        let sig = self.erased_sig(trait_ident, assoc_types);
+        quote!(#sig;)
+    }
+
+    /// Implementation of this method in `impl<P> ErasedFoo for Remember<P>`.
+    pub(crate) fn erased_impl(&self, trait_ident: &Ident, assoc_types: &[Ident]) -> TokenStream {
+        let sig = self.erased_sig(trait_ident, assoc_types);
+        let fn_ident = &self.sig.ident;
+        let arg_idents = self.args.iter().map(|a| &a.ident);
+        let this = match self.self_kind {
+            SelfKind::Ref => quote!(self.target()),
+            SelfKind::Mut => quote!(unsafe { self.target_mut() }),
+            SelfKind::Value => quote!(unsafe { ::dyner::dyn_ptr::Remember::take_target(self) }),
+        };
+        let mut call = quote!(<P::Target as #trait_ident>::#fn_ident(#this, #(#arg_idents),*));
+        if self.future.is_some() {
+            call = quote!(::dyner::__private::Box::pin(#call));
+        }
+        if let Some(gat_output) = &self.gat_output {
+            call = gat_output.erase(call);
+        }
+        if self.sig.unsafety.is_some() {
+            call = quote!(unsafe { #call });
+        }
+        quote! {
+            #sig {
+                #call
+            }
+        }
+    }
+
+    /// Implementation of this method in `impl Foo for DynFoo<'_>`, which
+    /// dispatches through the erased trait.
+    ///
+    /// `erased_ident` is `None` when implementing a supertrait `Foo` for the
+    /// `Dyn` type of a subtrait, whose erased trait has `ErasedFoo` as a
+    /// supertrait: we call `erased_foo` as a method then, as the macro that
+    /// generates that impl has no path to `ErasedFoo` that works wherever
+    /// it's invoked.
+    pub(crate) fn dyn_impl(&self, erased_ident: Option<&Ident>) -> TokenStream {
+        let sig = self.impl_sig();
+        let erased_fn_ident = self.erased_ident();
+        let conversions = self.args.iter().filter_map(|a| {
+            let ident = &a.ident;
+            let DynArg { dyn_path, mode } = a.dyn_arg.as_ref()?;
+            Some(match mode {
+                DynArgMode::Owned => quote!(let #ident = #dyn_path::new(#ident);),
+                DynArgMode::Ref(_) => quote!(let #ident = #dyn_path::from_ptr(#ident);),
+                DynArgMode::Mut(_) => quote!(let mut #ident = #dyn_path::from_ptr(#ident);),
+            })
+        });
+        let arg_exprs = self.args.iter().map(|a| {
+            let ident = &a.ident;
+            match a.dyn_arg.as_ref().map(|d| &d.mode) {
+                None | Some(DynArgMode::Owned) => quote!(#ident),
+                Some(DynArgMode::Ref(_)) => quote!(&*#ident),
+                Some(DynArgMode::Mut(_)) => quote!(&mut *#ident),
+            }
+        });
+        let (prelude, this) = match self.self_kind {
+            SelfKind::Ref => (quote!(), quote!(unsafe { &*self.ptr.as_ptr() })),
+            SelfKind::Mut => (quote!(), quote!(unsafe { &mut *self.ptr.as_mut_ptr() })),
+            SelfKind::Value => (
+                // Panics if this `DynFoo` doesn't own its object.
+                quote!(let mut this = self.ptr.into_owned();),
+                quote!(&mut *this.as_mut_ptr()),
+            ),
+        };
+        let conversions = quote!(#prelude #(#conversions)*);
+        let mut call = match erased_ident {
+            Some(erased_ident) => quote!(#erased_ident::#erased_fn_ident(#this, #(#arg_exprs),*)),
+            None => quote!((#this).#erased_fn_ident(#(#arg_exprs),*)),
+        };
+        if self.sig.unsafety.is_some() || matches!(self.self_kind, SelfKind::Value) {
+            call = quote!(unsafe { #call });
+        }
+        let body = if self.sig.asyncness.is_some() {
+            quote!(#conversions #call.await)
+        } else if self.future.is_some() && self.has_borrowed_dyn_args() {
+            // The boxed future borrows from the converted arguments, so
+            // they have to live inside the future we return.
+            quote!(async move { #conversions #call.await })
+        } else {
+            quote!(#conversions #call)
+        };
+        quote! {
+            #sig {
+                #body
+            }
+        }
+    }
+
+    fn has_borrowed_dyn_args(&self) -> bool {
+        self.args.iter().any(|a| {
+            matches!(
+                a.dyn_arg.as_ref().map(|d| &d.mode),
+                Some(DynArgMode::Ref(_) | DynArgMode::Mut(_))
+            )
+        })
+    }
+}
+
+impl<'t> TypeParam<'t> {
+    /// The type parameters of `sig`, each of which must be bounded by a
+    /// single trait (in its declaration or in the where clause).
+    fn from_sig(sig: &'t Signature) -> syn::Result<Vec<Self>> {
+        let mut type_params = vec![];
+        for param in &sig.generics.params {
+            let type_param = match param {
+                GenericParam::Lifetime(_) => continue,
+                GenericParam::Type(type_param) => type_param,
+                GenericParam::Const(_) => {
+                    return Err(syn::Error::new_spanned(
+                        param,
+                        "`#[dyner]` does not support const generic methods",
+                    ))
+                }
+            };
+            let where_bounds = sig
+                .generics
+                .where_clause
+                .iter()
+                .flat_map(|where_clause| &where_clause.predicates)
+                .filter_map(|predicate| match predicate {
+                    WherePredicate::Type(predicate)
+                        if is_type_param(&predicate.bounded_ty, &type_param.ident) =>
+                    {
+                        Some(&predicate.bounds)
+                    }
+                    _ => None,
+                })
+                .flatten();
+            let mut trait_paths =
+                type_param
+                    .bounds
+                    .iter()
+                    .chain(where_bounds)
+                    .filter_map(|bound| match bound {
+                        TypeParamBound::Trait(trait_bound)
+                            if !matches!(trait_bound.modifier, TraitBoundModifier::Maybe(_)) =>
+                        {
+                            Some(&trait_bound.path)
+                        }
+                        _ => None,
+                    });
+            match (trait_paths.next(), trait_paths.next()) {
+                (Some(trait_path), None) => type_params.push(TypeParam {
+                    ident: &type_param.ident,
+                    trait_path,
+                }),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        type_param,
+                        "`#[dyner]` only supports type parameters bounded by a single trait",
+                    ))
+                }
+            }
+        }
+        Ok(type_params)
+    }
+}
+
+fn is_type_param(ty: &Type, ident: &Ident) -> bool {
+    matches!(ty, Type::Path(p) if p.qself.is_none() && p.path.is_ident(ident))
+}
+
+impl DynArg {
+    fn from_type(ty: &Type, type_params: &[TypeParam<'_>]) -> syn::Result<Option<Self>> {
+        let (elem, mode) = match ty {
+            Type::Reference(r) if r.mutability.is_some() => {
+                (&*r.elem, DynArgMode::Mut(r.lifetime.clone()))
+            }
+            Type::Reference(r) => (&*r.elem, DynArgMode::Ref(r.lifetime.clone())),
+            _ => (ty, DynArgMode::Owned),
+        };
+
+        let trait_path = match elem {
+            Type::ImplTrait(impl_trait) => {
+                let mut trait_paths = impl_trait.bounds.iter().filter_map(|bound| match bound {
+                    TypeParamBound::Trait(trait_bound) => Some(&trait_bound.path),
+                    _ => None,
+                });
+                match (trait_paths.next(), trait_paths.next()) {
+                    (Some(trait_path), None) => trait_path,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            impl_trait,
+                            "`#[dyner]` only supports `impl Trait` arguments with a single trait",
+                        ))
+                    }
+                }
+            }
+            _ => match type_params.iter().find(|p| is_type_param(elem, p.ident)) {
+                Some(type_param) => type_param.trait_path,
+                None => return no_nested_erased_types(ty, type_params),
+            },
+        };
+
+        let mut dyn_path = trait_path.clone();
+        let last = dyn_path.segments.last_mut().unwrap();
+        if !last.arguments.is_empty() {
+            return Err(syn::Error::new_spanned(
+                trait_path,
+                "`#[dyner]` does not support generic arguments on `impl Trait` arguments",
+            ));
+        }
+        last.ident = format_ident!("Dyn{}", last.ident);
+
+        Ok(Some(DynArg { dyn_path, mode }))
+    }
+
+    /// The type of this argument in the erased trait.
+    fn erased_ty(&self) -> Type {
+        let dyn_path = &self.dyn_path;
+        match &self.mode {
+            DynArgMode::Owned => syn::parse_quote!(#dyn_path<'_>),
+            DynArgMode::Ref(lt) => syn::parse_quote!(&#lt #dyn_path<'_>),
+            DynArgMode::Mut(lt) => syn::parse_quote!(&#lt mut #dyn_path<'_>),
+        }
+    }
+}
+
+/// Reports an error if `impl Trait`, or a type parameter, appears somewhere
+/// in `ty` that we don't know how to erase (e.g., `Vec<impl Trait>`).
+fn no_nested_erased_types<T>(ty: &Type, type_params: &[TypeParam<'_>]) -> syn::Result<Option<T>> {
+    struct FindImplTrait<'ast>(Option<&'ast TypeImplTrait>);
+
+    impl<'ast> Visit<'ast> for FindImplTrait<'ast> {
+        fn visit_type_impl_trait(&mut self, impl_trait: &'ast TypeImplTrait) {
+            self.0.get_or_insert(impl_trait);
+        }
+    }
+
+    let mut find = FindImplTrait(None);
+    find.visit_type(ty);
+    match find.0 {
+        Some(impl_trait) => Err(syn::Error::new_spanned(
+            impl_trait,
+            "`#[dyner]` only supports `impl Trait`, `&impl Trait` and `&mut impl Trait` arguments",
+        )),
+        None => no_type_params(ty, type_params).map(|()| None),
+    }
+}
+
+/// Reports an error if a type parameter appears in `ty`.
+fn no_type_params(ty: &Type, type_params: &[TypeParam<'_>]) -> syn::Result<()> {
+    struct FindTypeParam<'p, 't, 'ast> {
+        type_params: &'p [TypeParam<'t>],
+        found: Option<&'ast Type>,
+    }
+
+    impl<'ast> Visit<'ast> for FindTypeParam<'_, '_, 'ast> {
+        fn visit_type(&mut self, ty: &'ast Type) {
+            if self.type_params.iter().any(|p| is_type_param(ty, p.ident)) {
+                self.found.get_or_insert(ty);
+            }
+            visit::visit_type(self, ty);
+        }
+    }
+
+    let mut find = FindTypeParam {
+        type_params,
+        found: None,
+    };
+    find.visit_type(ty);
+    match find.found {
+        Some(ty) => Err(syn::Error::new_spanned(
+            ty,
+            "`#[dyner]` only supports type parameters as the type of `V`, `&V` and `&mut V` \
+             arguments",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// `<Self as Bar>::Item` names a type of the underlying object that the
+/// erased trait, whose `Self` is a `Remember<P>`, can't name.
+fn no_supertrait_assoc_types(sig: &Signature, trait_ident: &Ident) -> syn::Result<()> {
+    struct FindSupertraitAssoc<'i, 'ast> {
+        trait_ident: &'i Ident,
+        found: Option<&'ast Type>,
+    }
+
+    impl<'ast> Visit<'ast> for FindSupertraitAssoc<'_, 'ast> {
+        fn visit_type(&mut self, ty: &'ast Type) {
+            if is_supertrait_assoc(ty, self.trait_ident) {
+                self.found.get_or_insert(ty);
+            }
+            visit::visit_type(self, ty);
+        }
+    }
+
+    let mut find = FindSupertraitAssoc {
+        trait_ident,
+        found: None,
+    };
+    find.visit_signature(sig);
+    match find.found {
+        Some(ty) => Err(syn::Error::new_spanned(
+            ty,
+            "`#[dyner]` does not support a supertrait's associated types in method signatures",
+        )),
+        None => Ok(()),
+    }
+}
+
+impl FutureOutput {
+    /// Extracts the output of `async fn foo() -> T` or
+    /// `fn foo() -> impl Future<Output = T>`.
+    fn from_sig(sig: &Signature) -> syn::Result<Option<Self>> {
+        if sig.asyncness.is_some() {
+            let output = match &sig.output {
+                ReturnType::Default => syn::parse_quote!(()),
+                ReturnType::Type(_, ty) => (**ty).clone(),
+            };
+            return Ok(Some(FutureOutput {
+                output,
+                bounds: vec![],
+            }));
+        }
+
+        let impl_trait = match &sig.output {
+            ReturnType::Type(_, ty) => match &**ty {
+                Type::ImplTrait(impl_trait) => impl_trait,
+                _ => return Ok(None),
+            },
+            ReturnType::Default => return Ok(None),
+        };
+
+        let mut output = None;
+        let mut bounds = vec![];
+        for bound in &impl_trait.bounds {
+            match bound {
+                TypeParamBound::Trait(trait_bound) => match future_output(&trait_bound.path) {
+                    Some(ty) => output = Some(ty.clone()),
+                    None => bounds.push(bound.clone()),
+                },
+                // The boxed future gets its own lifetime bound.
+                TypeParamBound::Lifetime(_) => {}
+                _ => bounds.push(bound.clone()),
+            }
+        }
+
+        match output {
+            Some(output) => Ok(Some(FutureOutput { output, bounds })),
+            None => Err(syn::Error::new_spanned(
+                impl_trait,
+                "`#[dyner]` only supports `impl Future<Output = ..>` in return position",
+            )),
+        }
+    }
+}
+
+/// If `path` is `Future<Output = T>` (or `std::future::Future<Output = T>`,
+/// etc.), returns `T`.
+pub(crate) fn future_output(path: &syn::Path) -> Option<&Type> {
+    let segment = path.segments.last()?;
+    if segment.ident != "Future" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::AssocType(assoc) if assoc.ident == "Output" => Some(&assoc.ty),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
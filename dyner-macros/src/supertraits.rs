@@ -0,0 +1,313 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    parse::Parser, punctuated::Punctuated, Ident, Path, Token, TraitBoundModifier, TypeParamBound,
+};
+
+/// A supertrait of a `#[dyner]` trait `Foo`.
+pub(crate) enum Supertrait {
+    /// Another `#[dyner]` trait `Bar`. `ErasedBar` becomes a supertrait of
+    /// `ErasedFoo`, so that `DynFoo` can implement `Bar` by dispatching
+    /// through its own vtable, and be upcast to `DynBar`. As with `impl Bar`
+    /// arguments, `DynBar` (and `ErasedBar`) must be nameable by the same
+    /// path as `Bar`.
+    Dyner(DynerSupertrait),
+    /// A standard trait, which `ErasedFoo` gets an extra method for.
+    Std(StdTrait),
+}
+
+pub(crate) struct DynerSupertrait {
+    trait_path: Path,
+    erased_path: Path,
+    dyn_path: Path,
+    /// `bar`, for `as_bar` and `into_bar`.
+    snake_name: String,
+}
+
+/// A standard trait that `DynFoo` forwards to the underlying object, either
+/// because it's a supertrait of `Foo` or because of `#[dyner(Debug)]`.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum StdTrait {
+    Debug,
+    Display,
+    /// Cloning a `DynFoo` clones the underlying object into a new `Box`;
+    /// cloning a `Ref<DynFoo>` clones the pointer it was constructed from
+    /// (see `CloneRef`).
+    Clone,
+}
+
+impl Supertrait {
+    pub(crate) fn new(bound: &TypeParamBound) -> syn::Result<Self> {
+        let trait_bound = match bound {
+            TypeParamBound::Trait(trait_bound)
+                if matches!(trait_bound.modifier, TraitBoundModifier::None)
+                    && trait_bound.lifetimes.is_none() =>
+            {
+                trait_bound
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    bound,
+                    "`#[dyner]` only supports supertraits that are traits",
+                ))
+            }
+        };
+        let trait_path = &trait_bound.path;
+        let last = trait_path.segments.last().unwrap();
+        if !last.arguments.is_empty() {
+            return Err(syn::Error::new_spanned(
+                trait_path,
+                "`#[dyner]` does not support generic arguments on supertraits",
+            ));
+        }
+
+        if let Some(std_trait) = StdTrait::from_path(trait_path) {
+            return Ok(Supertrait::Std(std_trait));
+        }
+        match last.ident.to_string().as_str() {
+            "Send" | "Sync" | "Sized" | "Unpin" | "Copy" => {
+                return Err(syn::Error::new_spanned(
+                    trait_path,
+                    format!(
+                        "`#[dyner]` does not support `{}` as a supertrait",
+                        last.ident
+                    ),
+                ))
+            }
+            _ => {}
+        }
+
+        let with_ident = |ident: Ident| {
+            let mut path = trait_path.clone();
+            path.segments.last_mut().unwrap().ident = ident;
+            path
+        };
+        Ok(Supertrait::Dyner(DynerSupertrait {
+            trait_path: trait_path.clone(),
+            erased_path: with_ident(format_ident!("Erased{}", last.ident)),
+            dyn_path: with_ident(format_ident!("Dyn{}", last.ident)),
+            snake_name: snake_case(&last.ident.to_string()),
+        }))
+    }
+}
+
+impl DynerSupertrait {
+    /// The bound on `ErasedFoo`.
+    pub(crate) fn erased_bound(&self) -> &Path {
+        &self.erased_path
+    }
+
+    /// `impl Bar for DynFoo<'data>`, generated by the macro that `Bar`'s
+    /// expansion exports as `DynBar` (see `DynerTrait::forward_macro`).
+    /// `impl_generics` are the generics of the impl, e.g. `<'data, Item>`,
+    /// and `dyn_ty` is `DynFoo<'data, Item>`.
+    pub(crate) fn forward(&self, impl_generics: &TokenStream, dyn_ty: &TokenStream) -> TokenStream {
+        let trait_path = &self.trait_path;
+        let dyn_path = &self.dyn_path;
+        quote! {
+            #dyn_path! {
+                impl #impl_generics #trait_path for #dyn_ty
+            }
+        }
+    }
+
+    /// `as_bar` and `into_bar`, in the inherent impl of `DynFoo`.
+    pub(crate) fn upcasts(&self) -> TokenStream {
+        let trait_path = &self.trait_path;
+        let dyn_path = &self.dyn_path;
+        let as_ident = format_ident!("as_{}", self.snake_name);
+        let into_ident = format_ident!("into_{}", self.snake_name);
+        let as_doc = format!("Borrows this object as a [`{}`].", quote!(#trait_path));
+        let into_doc = format!(
+            "Upcasts to a [`{}`], which owns the object if this does.",
+            quote!(#dyn_path)
+        );
+        quote! {
+            #[doc = #as_doc]
+            pub fn #as_ident(&self) -> ::dyner::dyner::Ref<#dyn_path<'_>> {
+                // Only given out behind `&`, and never dropped: we still own
+                // the pointer.
+                ::dyner::dyner::Ref::without_drop(unsafe {
+                    #dyn_path::__from_dyn_ptr(self.ptr.upcast_ref())
+                })
+            }
+
+            #[doc = #into_doc]
+            pub fn #into_ident(self) -> #dyn_path<'data> {
+                // The same pointer, so it allows `&mut` access if ours does.
+                unsafe { #dyn_path::__from_dyn_ptr(self.ptr.upcast()) }
+            }
+        }
+    }
+}
+
+impl StdTrait {
+    /// The arguments of `#[dyner(Debug, Clone)]`, which opt in to
+    /// forwarding those traits without making them supertraits.
+    pub(crate) fn parse_args(attr: TokenStream) -> syn::Result<Vec<Self>> {
+        let paths = Punctuated::<Path, Token![,]>::parse_terminated.parse2(attr)?;
+        paths
+            .iter()
+            .map(|path| {
+                StdTrait::from_path(path).ok_or_else(|| {
+                    syn::Error::new_spanned(
+                        path,
+                        "`#[dyner]` can only forward `Debug`, `Display` and `Clone`",
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn from_path(path: &Path) -> Option<Self> {
+        let last = path.segments.last()?;
+        if !last.arguments.is_empty() {
+            return None;
+        }
+        match last.ident.to_string().as_str() {
+            "Debug" => Some(StdTrait::Debug),
+            "Display" => Some(StdTrait::Display),
+            "Clone" => Some(StdTrait::Clone),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn path(self) -> TokenStream {
+        match self {
+            StdTrait::Debug => quote!(::core::fmt::Debug),
+            StdTrait::Display => quote!(::core::fmt::Display),
+            StdTrait::Clone => quote!(::core::clone::Clone),
+        }
+    }
+
+    /// The extra method for `Debug` or `Display`.
+    fn erased_fmt_ident(self) -> Ident {
+        match self {
+            StdTrait::Debug => format_ident!("erased_fmt_debug"),
+            StdTrait::Display => format_ident!("erased_fmt_display"),
+            StdTrait::Clone => unreachable!(),
+        }
+    }
+
+    /// Declaration of the extra methods in `ErasedFoo`, where `erased_ty` is
+    /// `ErasedFoo<Item, ..>`. `Clone` gets two, for cloning `DynFoo` and
+    /// `Ref<DynFoo>`, which can't return `Self`, so return a new `DynPtr`
+    /// instead. Cloning `DynFoo` needs a `Box`, so that one needs `alloc`.
+    pub(crate) fn erased_decl(self, erased_ty: &TokenStream) -> TokenStream {
+        match self {
+            StdTrait::Debug | StdTrait::Display => {
+                let erased_fn_ident = self.erased_fmt_ident();
+                quote! {
+                    fn #erased_fn_ident(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result;
+                }
+            }
+            StdTrait::Clone => quote! {
+                ::dyner::__if_alloc! {
+                    fn erased_clone<'data>(&self) -> ::dyner::dyn_ptr::DynPtr<dyn #erased_ty + 'data>
+                    where
+                        Self: 'data;
+                }
+
+                fn erased_clone_ref<'data>(&self) -> ::dyner::dyn_ptr::DynPtr<dyn #erased_ty + 'data>
+                where
+                    Self: 'data;
+            },
+        }
+    }
+
+    /// Implementation of the extra methods in
+    /// `impl<P> ErasedFoo for Remember<P>`.
+    pub(crate) fn erased_impl(self, erased_ty: &TokenStream) -> TokenStream {
+        match self {
+            StdTrait::Debug | StdTrait::Display => {
+                let path = self.path();
+                let erased_fn_ident = self.erased_fmt_ident();
+                quote! {
+                    fn #erased_fn_ident(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                        <P::Target as #path>::fmt(self.target(), f)
+                    }
+                }
+            }
+            StdTrait::Clone => quote! {
+                ::dyner::__if_alloc! {
+                    fn erased_clone<'data>(&self) -> ::dyner::dyn_ptr::DynPtr<dyn #erased_ty + 'data>
+                    where
+                        Self: 'data,
+                    {
+                        unsafe { ::dyner::dyn_ptr::DynPtr::new(self.clone_boxed()) }
+                    }
+                }
+
+                fn erased_clone_ref<'data>(&self) -> ::dyner::dyn_ptr::DynPtr<dyn #erased_ty + 'data>
+                where
+                    Self: 'data,
+                {
+                    // Only given out in a `Ref`, by `CloneRef::clone_ref`.
+                    unsafe { ::dyner::dyn_ptr::DynPtr::new(self.clone_ptr()) }
+                }
+            },
+        }
+    }
+
+    /// `impl Debug for DynFoo<'data>`, which dispatches through the extra
+    /// method. For `Clone`, also `impl CloneRef for DynFoo<'data>`.
+    pub(crate) fn dyn_impl(
+        self,
+        erased_ident: &Ident,
+        impl_generics: &TokenStream,
+        dyn_ty: &TokenStream,
+    ) -> TokenStream {
+        match self {
+            StdTrait::Debug | StdTrait::Display => {
+                let path = self.path();
+                let erased_fn_ident = self.erased_fmt_ident();
+                quote! {
+                    impl #impl_generics #path for #dyn_ty {
+                        fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                            #erased_ident::#erased_fn_ident(unsafe { &*self.ptr.as_ptr() }, f)
+                        }
+                    }
+                }
+            }
+            StdTrait::Clone => quote! {
+                ::dyner::__if_alloc! {
+                    impl #impl_generics ::core::clone::Clone for #dyn_ty {
+                        fn clone(&self) -> Self {
+                            Self {
+                                ptr: #erased_ident::erased_clone(unsafe { &*self.ptr.as_ptr() }),
+                            }
+                        }
+                    }
+                }
+
+                impl #impl_generics ::dyner::dyner::CloneRef for #dyn_ty {
+                    unsafe fn clone_ref(&self) -> Self {
+                        Self {
+                            ptr: #erased_ident::erased_clone_ref(&*self.ptr.as_ptr()),
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// `AsyncIter` to `async_iter`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
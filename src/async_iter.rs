@@ -1,6 +1,14 @@
-use crate::dyner::{Ref, RefMut};
-use std::future::Future;
-use std::pin::Pin;
+#[cfg(feature = "alloc")]
+use crate::dyn_ptr::{DynPtr, ErasedDrop, RawDeref, Remember};
+#[cfg(feature = "alloc")]
+use crate::flavor::{self, Flavor, Local};
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use core::alloc::Allocator;
+use core::future::Future;
+#[cfg(feature = "alloc")]
+use core::pin::Pin;
 
 pub trait AsyncIter {
     type Item;
@@ -25,156 +33,293 @@ where
     type Item = T::Item;
 
     type Next<'me>
+        = T::Next<'me>
     where
-        Self: 'me,
-    = T::Next<'me>;
+        Self: 'me;
 
     fn next(&mut self) -> Self::Next<'_> {
         T::next(self)
     }
 
     type SizeHint<'me>
+        = T::SizeHint<'me>
     where
-        Self: 'me,
-    = T::SizeHint<'me>;
+        Self: 'me;
 
     fn size_hint(&self) -> Self::SizeHint<'_> {
         T::size_hint(self)
     }
 }
 
-// May Athena forgive me for what I do here
-union FatPtr<'data, Item> {
-    raw: *mut (dyn ErasedAsyncIter<Item = Item> + 'data),
-    usizes: (usize, usize),
+/// An `AsyncIter` that uses dynamic dispatch. The flavor `F` selects
+/// whether it (and the futures it returns) are `Send`/`Sync`; see
+/// [`crate::flavor`]. The default flavor is neither:
+///
+/// ```compile_fail
+/// use dyner::async_iter::DynAsyncIter;
+/// # use dyner::async_iter::AsyncIter;
+/// # use std::future::{ready, Ready};
+/// #
+/// # struct Count(u32, u32);
+/// #
+/// # impl AsyncIter for Count {
+/// #     type Item = u32;
+/// #     type Next<'me> = Ready<Option<u32>>;
+/// #     fn next(&mut self) -> Ready<Option<u32>> {
+/// #         let next = (self.0 < self.1).then_some(self.0);
+/// #         self.0 += next.is_some() as u32;
+/// #         ready(next)
+/// #     }
+/// #     type SizeHint<'me> = Ready<Option<usize>>;
+/// #     fn size_hint(&self) -> Ready<Option<usize>> {
+/// #         ready(Some((self.1 - self.0) as usize))
+/// #     }
+/// # }
+/// #
+/// fn assert_send<T: Send>(_: T) {}
+/// assert_send(DynAsyncIter::new(Count(0, 10)));
+/// ```
+///
+/// but the same iterator can be constructed as `Send`:
+///
+/// ```
+/// use dyner::{async_iter::DynAsyncIter, flavor};
+/// # use dyner::async_iter::AsyncIter;
+/// # use std::future::{ready, Ready};
+/// #
+/// # struct Count(u32, u32);
+/// #
+/// # impl AsyncIter for Count {
+/// #     type Item = u32;
+/// #     type Next<'me> = Ready<Option<u32>>;
+/// #     fn next(&mut self) -> Ready<Option<u32>> {
+/// #         let next = (self.0 < self.1).then_some(self.0);
+/// #         self.0 += next.is_some() as u32;
+/// #         ready(next)
+/// #     }
+/// #     type SizeHint<'me> = Ready<Option<usize>>;
+/// #     fn size_hint(&self) -> Ready<Option<usize>> {
+/// #         ready(Some((self.1 - self.0) as usize))
+/// #     }
+/// # }
+/// #
+/// fn assert_send<T: Send>(_: T) {}
+/// let iter: DynAsyncIter<'_, u32, flavor::Send> = DynAsyncIter::new_flavored(Count(0, 10));
+/// assert_send(iter);
+/// ```
+///
+/// For those flavors, the iterator itself must be `'static`: we need its
+/// futures to be `Send` for every borrow of it, which the compiler can
+/// only prove for all lifetimes. A pointer to it can still borrow it, e.g.
+/// `from_ptr_flavored(&mut iter)`, but an iterator that borrows is
+/// rejected:
+///
+/// ```compile_fail
+/// use dyner::{async_iter::DynAsyncIter, flavor};
+/// # use dyner::async_iter::AsyncIter;
+/// # use std::future::{ready, Ready};
+/// #
+/// # struct Count(u32, u32);
+/// #
+/// # impl AsyncIter for Count {
+/// #     type Item = u32;
+/// #     type Next<'me> = Ready<Option<u32>>;
+/// #     fn next(&mut self) -> Ready<Option<u32>> {
+/// #         let next = (self.0 < self.1).then_some(self.0);
+/// #         self.0 += next.is_some() as u32;
+/// #         ready(next)
+/// #     }
+/// #     type SizeHint<'me> = Ready<Option<usize>>;
+/// #     fn size_hint(&self) -> Ready<Option<usize>> {
+/// #         ready(Some((self.1 - self.0) as usize))
+/// #     }
+/// # }
+/// #
+/// fn erase(iter: &mut Count) -> DynAsyncIter<'_, u32, flavor::Send> {
+///     DynAsyncIter::new_flavored(iter)
+/// }
+/// ```
+///
+/// Each call to `next` or `size_hint` boxes the returned future. To allocate
+/// once up front instead, see [`crate::dyn_async_iter::Cached`]; to not
+/// allocate at all, see [`crate::dyn_async_iter::DynAsyncIter::inline`].
+#[cfg(feature = "alloc")]
+pub struct DynAsyncIter<'data, Item, F: Flavor = Local> {
+    ptr: DynPtr<dyn ErasedAsyncIter<F, Item = Item> + 'data>,
 }
 
-impl<'data, Item> Copy for FatPtr<'data, Item> {}
+// Safety: the constructors for these flavors require the pointer and its
+// target to be `Send` (resp. the target to also be `Sync`).
+#[cfg(feature = "alloc")]
+unsafe impl<'data, Item> core::marker::Send for DynAsyncIter<'data, Item, flavor::Send> {}
+#[cfg(feature = "alloc")]
+unsafe impl<'data, Item> core::marker::Send for DynAsyncIter<'data, Item, flavor::Sync> {}
+#[cfg(feature = "alloc")]
+unsafe impl<'data, Item> core::marker::Sync for DynAsyncIter<'data, Item, flavor::Sync> {}
 
-impl<'data, Item> Clone for FatPtr<'data, Item> {
-    fn clone(&self) -> Self {
-        *self
-    }
+#[cfg(feature = "alloc")]
+#[doc(hidden)]
+pub trait ErasedAsyncIter<F: Flavor>: ErasedDrop {
+    type Item;
+    fn next<'me>(&'me mut self) -> F::BoxFuture<'me, Option<Self::Item>>
+    where
+        Self::Item: 'me;
+    fn size_hint<'me>(&'me self) -> F::BoxFuture<'me, Option<usize>>;
 }
 
-impl<'data, Item> FatPtr<'data, Item> {
-    fn new(raw: *mut (dyn ErasedAsyncIter<Item = Item> + 'data)) -> Self {
-        FatPtr { raw }
-    }
-
-    unsafe fn untagged(mut self) -> *mut (dyn ErasedAsyncIter<Item = Item> + 'data) {
-        self.usizes.0 &= !1;
-        self.raw
-    }
+#[cfg(feature = "alloc")]
+impl<P> ErasedAsyncIter<Local> for Remember<P>
+where
+    P: RawDeref,
+    P::Target: AsyncIter,
+{
+    type Item = <P::Target as AsyncIter>::Item;
 
-    unsafe fn is_tagged(self) -> bool {
-        (self.usizes.0 & 1) != 0
+    fn next<'me>(&'me mut self) -> Pin<Box<dyn Future<Output = Option<Self::Item>> + 'me>>
+    where
+        Self::Item: 'me,
+    {
+        Box::pin(AsyncIter::next(unsafe { self.target_mut() }))
     }
 
-    unsafe fn tagged(self) -> Self {
-        let (data, vtable) = self.usizes;
-        FatPtr {
-            usizes: (data | 1, vtable),
-        }
+    fn size_hint<'me>(&'me self) -> Pin<Box<dyn Future<Output = Option<usize>> + 'me>> {
+        Box::pin(AsyncIter::size_hint(self.target()))
     }
 }
 
-pub struct DynAsyncIter<'data, Item> {
-    fatptr: FatPtr<'data, Item>,
-}
+#[cfg(feature = "alloc")]
+impl<P> ErasedAsyncIter<flavor::Send> for Remember<P>
+where
+    P: RawDeref + Send,
+    P::Target: AsyncIter + Send + 'static,
+    for<'me> <P::Target as AsyncIter>::Next<'me>: Send,
+    for<'me> <P::Target as AsyncIter>::SizeHint<'me>: Send,
+{
+    type Item = <P::Target as AsyncIter>::Item;
 
-trait ErasedAsyncIter {
-    type Item;
-    fn next<'me>(&'me mut self) -> Pin<Box<dyn Future<Output = Option<Self::Item>> + 'me>>;
-    fn size_hint<'me>(&'me self) -> Pin<Box<dyn Future<Output = Option<usize>> + 'me>>;
+    fn next<'me>(&'me mut self) -> Pin<Box<dyn Future<Output = Option<Self::Item>> + Send + 'me>>
+    where
+        Self::Item: 'me,
+    {
+        Box::pin(AsyncIter::next(unsafe { self.target_mut() }))
+    }
+
+    fn size_hint<'me>(&'me self) -> Pin<Box<dyn Future<Output = Option<usize>> + Send + 'me>> {
+        Box::pin(AsyncIter::size_hint(self.target()))
+    }
 }
 
-impl<T> ErasedAsyncIter for T
+#[cfg(feature = "alloc")]
+impl<P> ErasedAsyncIter<flavor::Sync> for Remember<P>
 where
-    T: AsyncIter,
+    P: RawDeref + Send,
+    P::Target: AsyncIter + Send + Sync + 'static,
+    for<'me> <P::Target as AsyncIter>::Next<'me>: Send,
+    for<'me> <P::Target as AsyncIter>::SizeHint<'me>: Send,
 {
-    type Item = T::Item;
+    type Item = <P::Target as AsyncIter>::Item;
 
-    fn next<'me>(&'me mut self) -> Pin<Box<dyn Future<Output = Option<Self::Item>> + 'me>> {
-        Box::pin(AsyncIter::next(self))
+    fn next<'me>(&'me mut self) -> Pin<Box<dyn Future<Output = Option<Self::Item>> + Send + 'me>>
+    where
+        Self::Item: 'me,
+    {
+        Box::pin(AsyncIter::next(unsafe { self.target_mut() }))
     }
 
-    fn size_hint<'me>(&'me self) -> Pin<Box<dyn Future<Output = Option<usize>> + 'me>> {
-        Box::pin(AsyncIter::size_hint(self))
+    fn size_hint<'me>(&'me self) -> Pin<Box<dyn Future<Output = Option<usize>> + Send + 'me>> {
+        Box::pin(AsyncIter::size_hint(self.target()))
     }
 }
 
-impl<'data, Item> AsyncIter for DynAsyncIter<'data, Item> {
+#[cfg(feature = "alloc")]
+impl<'data, Item, F: Flavor> AsyncIter for DynAsyncIter<'data, Item, F> {
     type Item = Item;
 
     type Next<'me>
+        = F::BoxFuture<'me, Option<Item>>
     where
         Item: 'me,
         'data: 'me,
-    = Pin<Box<dyn Future<Output = Option<Item>> + 'me>>;
+        F: 'me;
 
     fn next(&mut self) -> Self::Next<'_> {
-        unsafe { ErasedAsyncIter::next(&mut *self.fatptr.untagged()) }
+        unsafe { ErasedAsyncIter::next(&mut *self.ptr.as_mut_ptr()) }
     }
 
     type SizeHint<'me>
+        = F::BoxFuture<'me, Option<usize>>
     where
         Item: 'me,
         'data: 'me,
-    = Pin<Box<dyn Future<Output = Option<usize>> + 'me>>;
+        F: 'me;
 
     fn size_hint(&self) -> Self::SizeHint<'_> {
-        unsafe { ErasedAsyncIter::size_hint(&*self.fatptr.untagged()) }
+        unsafe { ErasedAsyncIter::size_hint(&*self.ptr.as_ptr()) }
     }
 }
 
+#[cfg(feature = "alloc")]
 impl<'data, Item> DynAsyncIter<'data, Item> {
     pub fn new<T>(value: T) -> DynAsyncIter<'data, Item>
     where
         T: AsyncIter<Item = Item> + 'data,
         Item: 'data,
     {
-        let b: Box<dyn ErasedAsyncIter<Item = Item>> = Box::new(value);
-        let raw: *mut dyn ErasedAsyncIter<Item = Item> = Box::into_raw(b);
-        unsafe {
-            DynAsyncIter {
-                fatptr: FatPtr::new(raw).tagged(),
-            }
-        }
+        DynAsyncIter::from_ptr(Box::new(value))
     }
 
-    pub fn from_ref<T>(value: &'data T) -> Ref<DynAsyncIter<'data, Item>>
+    /// Like `new`, but boxes the iterator in `alloc`, which is kept with it
+    /// to free it on drop.
+    ///
+    /// The allocator is stored next to the pointer, so it must be at most a
+    /// word: a reference to a pool (e.g., `&Pool`) or a zero-sized allocator
+    /// like `Global`. A larger allocator fails to compile.
+    pub fn new_in<T, A>(value: T, alloc: A) -> DynAsyncIter<'data, Item>
     where
         T: AsyncIter<Item = Item> + 'data,
+        A: Allocator + 'data,
         Item: 'data,
     {
-        let v: &dyn ErasedAsyncIter<Item = Item> = value;
-        let raw: *const dyn ErasedAsyncIter<Item = Item> = v;
-        let raw: *mut dyn ErasedAsyncIter<Item = Item> = raw as *mut _;
-        Ref::new(DynAsyncIter {
-            fatptr: FatPtr::new(raw),
-        })
+        DynAsyncIter::from_ptr(Box::new_in(value, alloc))
     }
 
-    pub fn from_ref_mut<T>(value: &'data mut T) -> RefMut<DynAsyncIter<'data, Item>>
+    /// Constructs a `DynAsyncIter` from any [`RawDeref`] pointer, e.g.
+    /// `Box<T>` (giving an owned `DynAsyncIter`), `&mut T` (giving a
+    /// `RefMut<DynAsyncIter>`) or `Rc<T>` and `&T` (giving a
+    /// `Ref<DynAsyncIter>`).
+    pub fn from_ptr<P>(value: P) -> P::Wrapped<DynAsyncIter<'data, Item>>
     where
-        T: AsyncIter<Item = Item> + 'data,
+        P: RawDeref + 'data,
+        P::Target: AsyncIter<Item = Item>,
         Item: 'data,
     {
-        let v: &mut dyn ErasedAsyncIter<Item = Item> = value;
-        let raw: *mut dyn ErasedAsyncIter<Item = Item> = v;
-        RefMut::new(DynAsyncIter {
-            fatptr: FatPtr::new(raw),
-        })
+        DynAsyncIter::from_ptr_flavored(value)
     }
 }
 
-impl<'data, Item> Drop for DynAsyncIter<'data, Item> {
-    fn drop(&mut self) {
-        unsafe {
-            if self.fatptr.is_tagged() {
-                drop(Box::from_raw(self.fatptr.untagged()));
-            }
-        }
+/// Constructors for any flavor `F`, which is selected by annotating the
+/// result (e.g., `DynAsyncIter<'_, u32, flavor::Send>`). The pointer must
+/// satisfy the flavor's bounds.
+#[cfg(feature = "alloc")]
+impl<'data, Item, F: Flavor> DynAsyncIter<'data, Item, F> {
+    pub fn new_flavored<T>(value: T) -> DynAsyncIter<'data, Item, F>
+    where
+        T: 'data,
+        Remember<Box<T>>: ErasedAsyncIter<F, Item = Item>,
+        Item: 'data,
+    {
+        DynAsyncIter::from_ptr_flavored(Box::new(value))
+    }
+
+    pub fn from_ptr_flavored<P>(value: P) -> P::Wrapped<DynAsyncIter<'data, Item, F>>
+    where
+        P: RawDeref + 'data,
+        Remember<P>: ErasedAsyncIter<F, Item = Item>,
+        Item: 'data,
+    {
+        // Okay because `Wrapped` guards mutable access.
+        P::wrap(DynAsyncIter {
+            ptr: unsafe { DynPtr::new(Remember::new(value)) },
+        })
     }
 }
@@ -0,0 +1,48 @@
+//! A minimal bump arena over a fixed buffer, for allocating futures without
+//! touching the global allocator. Memory is only reclaimed when the arena
+//! is dropped.
+
+use std::alloc::{AllocError, Allocator, Layout};
+use std::cell::{Cell, UnsafeCell};
+use std::mem::MaybeUninit;
+use std::ptr::NonNull;
+
+pub struct Bump<const CAP: usize> {
+    buffer: UnsafeCell<[MaybeUninit<u8>; CAP]>,
+    used: Cell<usize>,
+}
+
+impl<const CAP: usize> Bump<CAP> {
+    pub fn new() -> Self {
+        Bump {
+            buffer: UnsafeCell::new([MaybeUninit::uninit(); CAP]),
+            used: Cell::new(0),
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+}
+
+unsafe impl<const CAP: usize> Allocator for Bump<CAP> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let base = self.buffer.get().cast::<u8>();
+        let free = unsafe { base.add(self.used.get()) };
+        // `align_offset` may give `usize::MAX` if it can't align `free`.
+        let start = self
+            .used
+            .get()
+            .checked_add(free.align_offset(layout.align()))
+            .ok_or(AllocError)?;
+        let end = start.checked_add(layout.size()).ok_or(AllocError)?;
+        if end > CAP {
+            return Err(AllocError);
+        }
+        self.used.set(end);
+        let ptr = unsafe { NonNull::new_unchecked(base.add(start)) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
@@ -0,0 +1,35 @@
+//! A global allocator that counts the allocations made on each thread, for
+//! checking that erased calls don't allocate.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::future::Future;
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Ignore allocations while the thread is being torn down.
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Runs `future`, returning the number of allocations it made on this
+/// thread.
+pub async fn count(future: impl Future<Output = ()>) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    future.await;
+    ALLOCATIONS.with(Cell::get) - before
+}
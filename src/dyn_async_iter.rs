@@ -1,71 +1,294 @@
+//! A `DynAsyncIter` whose futures are stored according to a strategy `S`,
+//! picked at the type level:
+//!
+//! * [`Boxing`] boxes each future, as `async_iter::DynAsyncIter` does.
+//! * [`Arena`] allocates each future in a caller-supplied allocator, such
+//!   as a request-scoped bump arena.
+//! * [`Small`] returns each future as a [`SmallFuture`], which stores it
+//!   inline if it fits in `N` bytes, and boxes it otherwise.
+//! * [`Cached`] boxes the iterator together with space for its futures, so
+//!   that there is one allocation up front and none per call.
+//! * [`Inline`] borrows an [`InlineAsyncIterImpl`] that the caller set up
+//!   (e.g., on the stack), so there are no allocations at all; see
+//!   [`DynAsyncIter::inline`].
+//!
+//! Whatever the strategy, a `DynAsyncIter<'_, S>` is used the same way.
+
 use crate::async_iter::AsyncIter;
-use std::future::Future;
+#[cfg(feature = "alloc")]
+use crate::dyner::SmallFuture;
+use crate::dyner::{FatPtr, InlineFuture, RefMut};
+use crate::inline_async_iter::{InlineAsyncIter, InlineAsyncIterImpl, SlotsInUse};
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use core::alloc::Allocator;
+use core::future::Future;
+use core::marker::PhantomData;
+#[cfg(feature = "alloc")]
+use core::mem::MaybeUninit;
+use core::pin::Pin;
 
 pub struct DynAsyncIter<'me, S: DynAsyncIterStrategy> {
-    dyn_trait: DynAsyncIterPtr<'me, S>,
+    dyn_trait: FatPtr<dyn DynAsyncIterTrait<S> + 'me>,
 }
 
-// May Athena forgive me for what I do here
-union DynAsyncIterPtr<'me, S: DynAsyncIterStrategy> {
-    raw: *mut (dyn DynAsyncIterTrait<S> + 'me),
-    usizes: (usize, usize),
+trait DynAsyncIterTrait<S: DynAsyncIterStrategy> {
+    fn next(&mut self) -> S::Next<'_>;
+    fn size_hint(&self) -> S::SizeHint<'_>;
+    fn try_size_hint(&self) -> Result<S::SizeHint<'_>, SlotsInUse>;
+
+    /// Drops and frees an owned object; by default, one that was boxed in
+    /// the global allocator.
+    ///
+    /// Unsafe: `self` must be owned, and not used afterwards.
+    #[cfg(feature = "alloc")]
+    unsafe fn drop_owned(self: *mut Self) {
+        drop(Box::from_raw(self))
+    }
 }
 
-impl<'me, S> Copy for DynAsyncIterPtr<'me, S> where S: DynAsyncIterStrategy {}
+/// An object boxed in the allocator `A`, which it keeps a copy of so that
+/// it can free itself.
+#[cfg(feature = "alloc")]
+struct Allocated<T, A> {
+    value: T,
+    /// Always initialized, except while being constructed and dropped.
+    alloc: MaybeUninit<A>,
+}
 
-impl<'me, S> Clone for DynAsyncIterPtr<'me, S>
+#[cfg(feature = "alloc")]
+impl<S, T, A> DynAsyncIterTrait<S> for Allocated<T, A>
 where
     S: DynAsyncIterStrategy,
+    T: DynAsyncIterTrait<S>,
+    A: Allocator,
 {
-    fn clone(&self) -> Self {
-        *self
+    fn next(&mut self) -> S::Next<'_> {
+        self.value.next()
+    }
+
+    fn size_hint(&self) -> S::SizeHint<'_> {
+        self.value.size_hint()
+    }
+
+    fn try_size_hint(&self) -> Result<S::SizeHint<'_>, SlotsInUse> {
+        self.value.try_size_hint()
+    }
+
+    unsafe fn drop_owned(self: *mut Self) {
+        let alloc = (*self).alloc.assume_init_read();
+        drop(Box::from_raw_in(self, alloc))
     }
 }
 
-impl<'me, S> DynAsyncIterPtr<'me, S>
+pub trait DynAsyncIterStrategy {
+    type Item;
+
+    type Next<'a>: Future<Output = Option<Self::Item>>
+    where
+        Self: 'a;
+
+    type SizeHint<'a>: Future<Output = Option<usize>>
+    where
+        Self: 'a;
+}
+
+/// Each future is boxed.
+#[cfg(feature = "alloc")]
+pub struct Boxing<Item> {
+    _item: PhantomData<Item>,
+}
+
+#[cfg(feature = "alloc")]
+impl<Item> DynAsyncIterStrategy for Boxing<Item> {
+    type Item = Item;
+
+    type Next<'a>
+        = Pin<Box<dyn Future<Output = Option<Item>> + 'a>>
+    where
+        Self: 'a;
+
+    type SizeHint<'a>
+        = Pin<Box<dyn Future<Output = Option<usize>> + 'a>>
+    where
+        Self: 'a;
+}
+
+#[cfg(feature = "alloc")]
+impl<I> DynAsyncIterTrait<Boxing<I::Item>> for I
 where
-    S: DynAsyncIterStrategy,
+    I: AsyncIter,
 {
-    fn new(raw: *mut (dyn DynAsyncIterTrait<S> + 'me)) -> Self {
-        DynAsyncIterPtr { raw }
+    fn next(&mut self) -> Pin<Box<dyn Future<Output = Option<I::Item>> + '_>> {
+        Box::pin(AsyncIter::next(self))
     }
 
-    unsafe fn untagged(mut self) -> *mut (dyn DynAsyncIterTrait<S> + 'me) {
-        self.usizes.0 &= !1;
-        self.raw
+    fn size_hint(&self) -> Pin<Box<dyn Future<Output = Option<usize>> + '_>> {
+        Box::pin(AsyncIter::size_hint(self))
     }
 
-    unsafe fn is_tagged(self) -> bool {
-        (self.usizes.0 & 1) != 0
+    fn try_size_hint(
+        &self,
+    ) -> Result<Pin<Box<dyn Future<Output = Option<usize>> + '_>>, SlotsInUse> {
+        Ok(Box::pin(AsyncIter::size_hint(self)))
     }
+}
 
-    unsafe fn tagged(self) -> Self {
-        let (data, vtable) = self.usizes;
-        DynAsyncIterPtr {
-            usizes: (data | 1, vtable),
-        }
-    }
+/// Each future is boxed in the allocator `A`, which is borrowed for `'a`.
+#[cfg(feature = "alloc")]
+pub struct Arena<'a, Item, A: Allocator> {
+    _marker: PhantomData<(Item, &'a A)>,
 }
 
-trait DynAsyncIterTrait<S: DynAsyncIterStrategy> {
-    fn next(&mut self) -> S::Next<'_>;
-    fn size_hint(&self) -> S::SizeHint<'_>;
+#[cfg(feature = "alloc")]
+impl<'a, Item, A: Allocator> DynAsyncIterStrategy for Arena<'a, Item, A> {
+    type Item = Item;
+
+    type Next<'b>
+        = Pin<Box<dyn Future<Output = Option<Item>> + 'b, &'a A>>
+    where
+        Self: 'b;
+
+    type SizeHint<'b>
+        = Pin<Box<dyn Future<Output = Option<usize>> + 'b, &'a A>>
+    where
+        Self: 'b;
 }
 
-pub trait DynAsyncIterStrategy {
-    type DynType: ?Sized;
+/// An iterator along with the allocator to box its futures in.
+#[cfg(feature = "alloc")]
+struct InArena<'a, I, A> {
+    iter: I,
+    alloc: &'a A,
+}
 
-    type Item;
+/// Okay as long as the future isn't leaked (see `DynAsyncIter::new_in_arena`):
+/// then it's dropped before its memory is freed.
+#[cfg(feature = "alloc")]
+fn pin_in<'a, 'b, F, A>(
+    future: F,
+    alloc: &'a A,
+) -> Pin<Box<dyn Future<Output = F::Output> + 'b, &'a A>>
+where
+    F: Future + 'b,
+    A: Allocator,
+{
+    let boxed: Box<dyn Future<Output = F::Output> + 'b, &'a A> = Box::new_in(future, alloc);
+    unsafe { Pin::new_unchecked(boxed) }
+}
 
-    type Next<'a>: Future<Output = Option<Self::Item>>
+#[cfg(feature = "alloc")]
+impl<'a, I, A> DynAsyncIterTrait<Arena<'a, I::Item, A>> for InArena<'a, I, A>
+where
+    I: AsyncIter,
+    A: Allocator,
+{
+    fn next(&mut self) -> Pin<Box<dyn Future<Output = Option<I::Item>> + '_, &'a A>> {
+        pin_in(self.iter.next(), self.alloc)
+    }
+
+    fn size_hint(&self) -> Pin<Box<dyn Future<Output = Option<usize>> + '_, &'a A>> {
+        pin_in(self.iter.size_hint(), self.alloc)
+    }
+
+    fn try_size_hint(
+        &self,
+    ) -> Result<Pin<Box<dyn Future<Output = Option<usize>> + '_, &'a A>>, SlotsInUse> {
+        Ok(pin_in(self.iter.size_hint(), self.alloc))
+    }
+}
+
+/// Futures are stored in a [`SmallFuture`], inline if they fit in `N` bytes.
+#[cfg(feature = "alloc")]
+pub struct Small<Item, const N: usize> {
+    _item: PhantomData<Item>,
+}
+
+#[cfg(feature = "alloc")]
+impl<Item, const N: usize> DynAsyncIterStrategy for Small<Item, N> {
+    type Item = Item;
+
+    type Next<'a>
+        = SmallFuture<'a, Option<Item>, N>
     where
         Self: 'a;
 
-    type SizeHint<'a>: Future<Output = Option<usize>>
+    type SizeHint<'a>
+        = SmallFuture<'a, Option<usize>, N>
     where
         Self: 'a;
 }
 
+#[cfg(feature = "alloc")]
+impl<I, const N: usize> DynAsyncIterTrait<Small<I::Item, N>> for I
+where
+    I: AsyncIter,
+{
+    fn next(&mut self) -> SmallFuture<'_, Option<I::Item>, N> {
+        SmallFuture::new(AsyncIter::next(self))
+    }
+
+    fn size_hint(&self) -> SmallFuture<'_, Option<usize>, N> {
+        SmallFuture::new(AsyncIter::size_hint(self))
+    }
+
+    fn try_size_hint(&self) -> Result<SmallFuture<'_, Option<usize>, N>, SlotsInUse> {
+        Ok(SmallFuture::new(AsyncIter::size_hint(self)))
+    }
+}
+
+/// Futures are stored in an `InlineAsyncIterImpl` borrowed from the caller.
+pub struct Inline<Item> {
+    _item: PhantomData<Item>,
+}
+
+/// Futures are stored in an `InlineAsyncIterImpl` that is boxed along with
+/// the iterator, and reused by each call.
+pub struct Cached<Item> {
+    _item: PhantomData<Item>,
+}
+
+macro_rules! inline_strategy {
+    ($strategy:ident) => {
+        impl<Item> DynAsyncIterStrategy for $strategy<Item> {
+            type Item = Item;
+
+            type Next<'a>
+                = InlineFuture<'a, Option<Item>>
+            where
+                Self: 'a;
+
+            type SizeHint<'a>
+                = InlineFuture<'a, Option<usize>>
+            where
+                Self: 'a;
+        }
+
+        // Every `InlineAsyncIterImpl` we hold is pinned: `Cached::new` boxes
+        // it, and `DynAsyncIter::inline` takes it as `Pin<&mut _>`.
+        impl<'me, I> DynAsyncIterTrait<$strategy<I::Item>> for InlineAsyncIterImpl<'me, I>
+        where
+            I: AsyncIter + 'me,
+        {
+            fn next(&mut self) -> InlineFuture<'_, Option<I::Item>> {
+                InlineAsyncIter::next(unsafe { Pin::new_unchecked(self) })
+            }
+
+            fn size_hint(&self) -> InlineFuture<'_, Option<usize>> {
+                InlineAsyncIter::size_hint(unsafe { Pin::new_unchecked(self) })
+            }
+
+            fn try_size_hint(&self) -> Result<InlineFuture<'_, Option<usize>>, SlotsInUse> {
+                InlineAsyncIter::try_size_hint(unsafe { Pin::new_unchecked(self) })
+            }
+        }
+    };
+}
+
+inline_strategy!(Inline);
+inline_strategy!(Cached);
+
 impl<'me, S> AsyncIter for DynAsyncIter<'me, S>
 where
     S: DynAsyncIterStrategy,
@@ -73,20 +296,340 @@ where
     type Item = S::Item;
 
     type Next<'a>
+        = S::Next<'a>
     where
-        Self: 'a,
-    = S::Next<'a>;
+        Self: 'a;
 
     fn next(&mut self) -> Self::Next<'_> {
-        unsafe { DynAsyncIterTrait::next(&mut *self.dyn_trait.untagged()) }
+        unsafe { DynAsyncIterTrait::next(&mut *self.dyn_trait.as_ptr()) }
     }
 
     type SizeHint<'a>
+        = S::SizeHint<'a>
     where
-        Self: 'a,
-    = S::SizeHint<'a>;
+        Self: 'a;
 
     fn size_hint(&self) -> Self::SizeHint<'_> {
-        unsafe { DynAsyncIterTrait::size_hint(&*self.dyn_trait.untagged()) }
+        unsafe { DynAsyncIterTrait::size_hint(&*self.dyn_trait.as_ptr()) }
+    }
+}
+
+impl<'me, S: DynAsyncIterStrategy> DynAsyncIter<'me, S> {
+    /// Like `size_hint`, but returns an error instead of boxing the future
+    /// if all the iterator's slots are in use. Never fails for [`Boxing`].
+    pub fn try_size_hint(&self) -> Result<S::SizeHint<'_>, SlotsInUse> {
+        unsafe { DynAsyncIterTrait::try_size_hint(&*self.dyn_trait.as_ptr()) }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn owned<T>(value: T) -> Self
+    where
+        T: DynAsyncIterTrait<S> + 'me,
+    {
+        let b: Box<dyn DynAsyncIterTrait<S> + 'me> = Box::new(value);
+        DynAsyncIter {
+            dyn_trait: FatPtr::owned(Box::into_raw(b)),
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn owned_in<T, A>(value: T, alloc: A) -> Self
+    where
+        T: DynAsyncIterTrait<S> + 'me,
+        A: Allocator + 'me,
+    {
+        let b = Box::new_in(
+            Allocated {
+                value,
+                alloc: MaybeUninit::uninit(),
+            },
+            alloc,
+        );
+        let (raw, alloc) = Box::into_raw_with_allocator(b);
+        unsafe { (*raw).alloc.write(alloc) };
+        let raw: *mut (dyn DynAsyncIterTrait<S> + 'me) = raw;
+        DynAsyncIter {
+            dyn_trait: FatPtr::owned(raw),
+        }
+    }
+
+    fn borrowed<T>(value: &'me mut T) -> RefMut<Self>
+    where
+        T: DynAsyncIterTrait<S> + 'me,
+    {
+        let v: &mut (dyn DynAsyncIterTrait<S> + 'me) = value;
+        RefMut::new(DynAsyncIter {
+            dyn_trait: FatPtr::new(v),
+        })
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'me, Item> DynAsyncIter<'me, Boxing<Item>> {
+    pub fn new<I>(value: I) -> Self
+    where
+        I: AsyncIter<Item = Item> + 'me,
+    {
+        DynAsyncIter::owned(value)
     }
+
+    /// Like `new`, but boxes the object in `alloc`, which is kept with it
+    /// to free it on drop.
+    pub fn new_in<I, A>(value: I, alloc: A) -> Self
+    where
+        I: AsyncIter<Item = Item> + 'me,
+        A: Allocator + 'me,
+    {
+        DynAsyncIter::owned_in(value, alloc)
+    }
+
+    pub fn from_mut<I>(value: &'me mut I) -> RefMut<Self>
+    where
+        I: AsyncIter<Item = Item> + 'me,
+    {
+        DynAsyncIter::borrowed(value)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'me, 'a: 'me, Item, A: Allocator> DynAsyncIter<'me, Arena<'a, Item, A>> {
+    /// Boxes each future `value` returns in `alloc`. The object itself is
+    /// boxed in the global allocator.
+    ///
+    /// # Safety
+    ///
+    /// No future returned by `next` (or `size_hint`) may be leaked (e.g.,
+    /// with `mem::forget`) while `'a` is live: the futures are pinned, so
+    /// must be dropped before `alloc` may free their memory. A bump arena
+    /// that is reset or dropped after a request is such an allocator.
+    pub unsafe fn new_in_arena<I>(value: I, alloc: &'a A) -> Self
+    where
+        I: AsyncIter<Item = Item> + 'me,
+    {
+        DynAsyncIter::owned(InArena { iter: value, alloc })
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'me, Item, const N: usize> DynAsyncIter<'me, Small<Item, N>> {
+    pub fn new<I>(value: I) -> Self
+    where
+        I: AsyncIter<Item = Item> + 'me,
+    {
+        DynAsyncIter::owned(value)
+    }
+
+    /// Like `new`, but boxes the object in `alloc`, which is kept with it
+    /// to free it on drop.
+    pub fn new_in<I, A>(value: I, alloc: A) -> Self
+    where
+        I: AsyncIter<Item = Item> + 'me,
+        A: Allocator + 'me,
+    {
+        DynAsyncIter::owned_in(value, alloc)
+    }
+
+    pub fn from_mut<I>(value: &'me mut I) -> RefMut<Self>
+    where
+        I: AsyncIter<Item = Item> + 'me,
+    {
+        DynAsyncIter::borrowed(value)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'me, Item> DynAsyncIter<'me, Cached<Item>> {
+    pub fn new<I>(value: I) -> Self
+    where
+        I: AsyncIter<Item = Item> + 'me,
+    {
+        DynAsyncIter::owned(InlineAsyncIterImpl::new(value))
+    }
+
+    /// Like `new`, but boxes the object in `alloc`, which is kept with it
+    /// to free it on drop.
+    pub fn new_in<I, A>(value: I, alloc: A) -> Self
+    where
+        I: AsyncIter<Item = Item> + 'me,
+        A: Allocator + 'me,
+    {
+        DynAsyncIter::owned_in(InlineAsyncIterImpl::new(value), alloc)
+    }
+}
+
+impl<'me, Item> DynAsyncIter<'me, Inline<Item>> {
+    /// Borrows `storage`, which holds both the iterator and space for its
+    /// futures, so that no allocation is needed. The futures are polled in
+    /// place, so `storage` must be pinned:
+    ///
+    /// ```
+    /// use dyner::async_iter::AsyncIter;
+    /// use dyner::dyn_async_iter::DynAsyncIter;
+    /// use dyner::inline_async_iter::InlineAsyncIterImpl;
+    /// use std::pin::pin;
+    /// # use std::future::{ready, Ready};
+    /// #
+    /// # struct Count(u32, u32);
+    /// #
+    /// # impl AsyncIter for Count {
+    /// #     type Item = u32;
+    /// #     type Next<'me> = Ready<Option<u32>>;
+    /// #     fn next(&mut self) -> Ready<Option<u32>> {
+    /// #         let next = (self.0 < self.1).then_some(self.0);
+    /// #         self.0 += next.is_some() as u32;
+    /// #         ready(next)
+    /// #     }
+    /// #     type SizeHint<'me> = Ready<Option<usize>>;
+    /// #     fn size_hint(&self) -> Ready<Option<usize>> {
+    /// #         ready(Some((self.1 - self.0) as usize))
+    /// #     }
+    /// # }
+    /// #
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// let storage = pin!(InlineAsyncIterImpl::new(Count(0, 3)));
+    /// let mut iter = DynAsyncIter::inline(storage);
+    /// assert_eq!(iter.next().await, Some(0));
+    /// # });
+    /// ```
+    pub fn inline<'i, I>(storage: Pin<&'me mut InlineAsyncIterImpl<'i, I>>) -> RefMut<Self>
+    where
+        I: AsyncIter<Item = Item> + 'i,
+        'i: 'me,
+    {
+        // We never move out of `storage` (see `inline_strategy!`).
+        DynAsyncIter::borrowed(unsafe { storage.get_unchecked_mut() })
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'me, S: DynAsyncIterStrategy> Drop for DynAsyncIter<'me, S> {
+    fn drop(&mut self) {
+        if self.dyn_trait.is_owned() {
+            unsafe { DynAsyncIterTrait::drop_owned(self.dyn_trait.as_ptr()) }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+async fn do_loop<S>(range: std::ops::Range<u32>, data: &mut DynAsyncIter<'_, S>)
+where
+    S: DynAsyncIterStrategy<Item = u32>,
+{
+    assert_eq!(AsyncIter::size_hint(data).await, Some(range.len()));
+    for i in range {
+        assert_eq!(AsyncIter::next(data).await, Some(i));
+    }
+    assert_eq!(AsyncIter::next(data).await, None);
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn boxing_new() {
+    let range = crate::yielding_range::YieldingRange::new(0, 10);
+    let mut dyn_range = DynAsyncIter::<Boxing<_>>::new(range);
+    do_loop(0..10, &mut dyn_range).await;
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn boxing_from_mut() {
+    let mut range = crate::yielding_range::YieldingRange::new(0, 10);
+    do_loop(0..10, &mut DynAsyncIter::<Boxing<_>>::from_mut(&mut range)).await;
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn boxing_new_in() {
+    let range = crate::yielding_range::YieldingRange::new(0, 10);
+    let mut dyn_range = DynAsyncIter::<Boxing<_>>::new_in(range, std::alloc::Global);
+    do_loop(0..10, &mut dyn_range).await;
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn small_new() {
+    let range = crate::yielding_range::YieldingRange::new(0, 10);
+    let mut dyn_range = DynAsyncIter::<Small<_, 64>>::new(range);
+    assert!(AsyncIter::next(&mut dyn_range).is_inline());
+    do_loop(0..10, &mut dyn_range).await;
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn small_from_mut_boxed() {
+    let mut range = crate::yielding_range::YieldingRange::new(0, 10);
+    let mut dyn_range = DynAsyncIter::<Small<_, 1>>::from_mut(&mut range);
+    assert!(!AsyncIter::size_hint(&*dyn_range).is_inline());
+    do_loop(0..10, &mut dyn_range).await;
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn arena_new() {
+    let range = crate::yielding_range::YieldingRange::new(0, 10);
+    let mut dyn_range = unsafe { DynAsyncIter::new_in_arena(range, &std::alloc::Global) };
+    do_loop(0..10, &mut dyn_range).await;
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn cached_try_size_hint() {
+    let range = crate::yielding_range::YieldingRange::new(0, 10);
+    let dyn_range = DynAsyncIter::<Cached<_>>::new(range);
+    let hints: Vec<_> = (0..crate::inline_async_iter::SIZE_HINT_SLOTS)
+        .map(|_| dyn_range.try_size_hint().unwrap())
+        .collect();
+    assert_eq!(dyn_range.try_size_hint().err(), Some(SlotsInUse));
+    assert_eq!(AsyncIter::size_hint(&dyn_range).await, Some(10));
+    drop(hints);
+    assert_eq!(dyn_range.try_size_hint().unwrap().await, Some(10));
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn cached_new() {
+    let range = crate::yielding_range::YieldingRange::new(0, 10);
+    let mut dyn_range = DynAsyncIter::<Cached<_>>::new(range);
+    do_loop(0..10, &mut dyn_range).await;
+}
+
+/// An allocator that counts its live allocations.
+#[cfg(all(test, feature = "std"))]
+#[derive(Clone, Copy)]
+struct Live<'a>(&'a std::cell::Cell<usize>);
+
+#[cfg(all(test, feature = "std"))]
+unsafe impl Allocator for Live<'_> {
+    fn allocate(
+        &self,
+        layout: std::alloc::Layout,
+    ) -> Result<std::ptr::NonNull<[u8]>, std::alloc::AllocError> {
+        self.0.set(self.0.get() + 1);
+        std::alloc::Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: std::ptr::NonNull<u8>, layout: std::alloc::Layout) {
+        self.0.set(self.0.get() - 1);
+        std::alloc::Global.deallocate(ptr, layout)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn cached_new_in() {
+    let live = std::cell::Cell::new(0);
+    let range = crate::yielding_range::YieldingRange::new(0, 10);
+    let mut dyn_range = DynAsyncIter::<Cached<_>>::new_in(range, Live(&live));
+    assert_eq!(live.get(), 1);
+    do_loop(0..10, &mut dyn_range).await;
+    drop(dyn_range);
+    assert_eq!(live.get(), 0);
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn inline() {
+    let range = crate::yielding_range::YieldingRange::new(0, 10);
+    let inline_range = std::pin::pin!(InlineAsyncIterImpl::new(range));
+    do_loop(0..10, &mut DynAsyncIter::inline(inline_range)).await;
 }
@@ -0,0 +1,542 @@
+//! Erasing smart pointers. A `Dyn` type (e.g., `DynFoo<'data>`) can be
+//! constructed from any pointer `P` that implements [`RawDeref`]: we convert
+//! `P` into a raw pointer to its target and wrap that in a `Remember<P>`,
+//! which remembers in its type what `P` was. Unsizing that to
+//! `dyn ErasedFoo` gives us a vtable that knows how to drop the original `P`
+//! (see [`ErasedDrop`]). As the raw pointer may itself be wide (e.g.,
+//! `*const [T]`), the `Remember<P>` is stored inline in a [`DynPtr`].
+
+use crate::dyner::{Ref, RefMut};
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, rc::Rc, sync::Arc};
+#[cfg(feature = "alloc")]
+use core::alloc::Allocator;
+use core::{
+    marker::{PhantomData, Unsize},
+    mem::{align_of, size_of, ManuallyDrop, MaybeUninit},
+    ops::Deref,
+    pin::Pin,
+    ptr::{self, Pointee},
+};
+
+/// A pointer that can be converted to and from a raw pointer to its target.
+///
+/// The raw pointer is `*mut` so that pointers that give out `&mut Target`
+/// can hand over their write permission; shared pointers must never be
+/// written through.
+///
+/// Unsafe: implementors promise that
+///
+/// * `from_raw(into_raw(p))` gives back `p`;
+/// * `Wrapped<D>` only gives out `&mut D` if `P` gives out `&mut Target`;
+/// * `OWNED` is only true if `P` is `Box<Target, A>`.
+pub unsafe trait RawDeref: Deref + Sized {
+    /// What a `Dyn` type `D` constructed from this pointer is wrapped in:
+    /// `D` itself if the pointer owns its target, `RefMut<D>` if it only
+    /// allows mutation, and `Ref<D>` otherwise.
+    type Wrapped<D>;
+
+    /// True if the `Dyn` type owns its target, so that `self` methods can be
+    /// called on it.
+    const OWNED: bool = false;
+
+    /// Whatever else is needed to rebuild the pointer from the raw one: the
+    /// allocator of a `Box<T, A>`, and `()` otherwise.
+    type Alloc;
+
+    fn wrap<D>(d: D) -> Self::Wrapped<D>;
+
+    fn into_raw(this: Self) -> (*mut Self::Target, Self::Alloc);
+
+    /// Unsafe: `target` and `alloc` must have been returned from `into_raw`
+    unsafe fn from_raw(target: *mut Self::Target, alloc: Self::Alloc) -> Self;
+
+    /// Moves the target out and frees it, for calling a `self` method.
+    ///
+    /// Unsafe: as for `from_raw`, and `OWNED` must be true.
+    unsafe fn take_raw(target: *mut Self::Target, alloc: Self::Alloc) -> Self::Target
+    where
+        Self::Target: Sized,
+    {
+        let _ = (target, alloc);
+        // Only a `Box` is owned.
+        unreachable!()
+    }
+}
+
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized> RawDeref for Rc<T> {
+    type Wrapped<D> = Ref<D>;
+
+    type Alloc = ();
+
+    fn wrap<D>(d: D) -> Ref<D> {
+        Ref::new(d)
+    }
+
+    fn into_raw(this: Self) -> (*mut T, ()) {
+        (Rc::into_raw(this).cast_mut(), ())
+    }
+
+    unsafe fn from_raw(target: *mut T, (): ()) -> Self {
+        Rc::from_raw(target)
+    }
+}
+
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized> RawDeref for Arc<T> {
+    type Wrapped<D> = Ref<D>;
+
+    type Alloc = ();
+
+    fn wrap<D>(d: D) -> Ref<D> {
+        Ref::new(d)
+    }
+
+    fn into_raw(this: Self) -> (*mut T, ()) {
+        (Arc::into_raw(this).cast_mut(), ())
+    }
+
+    unsafe fn from_raw(target: *mut T, (): ()) -> Self {
+        Arc::from_raw(target)
+    }
+}
+
+/// The allocator is kept alongside the raw pointer, in the two words a
+/// [`DynPtr`] has room for: so `A` must be zero-sized (e.g., `Global`) if
+/// `T` is unsized, and at most a word (e.g., `&Bump`) otherwise.
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized, A: Allocator> RawDeref for Box<T, A> {
+    type Wrapped<D> = D;
+
+    const OWNED: bool = true;
+
+    type Alloc = A;
+
+    fn wrap<D>(d: D) -> D {
+        d
+    }
+
+    fn into_raw(this: Self) -> (*mut T, A) {
+        Box::into_raw_with_allocator(this)
+    }
+
+    unsafe fn from_raw(target: *mut T, alloc: A) -> Self {
+        Box::from_raw_in(target, alloc)
+    }
+
+    unsafe fn take_raw(target: *mut T, alloc: A) -> T
+    where
+        T: Sized,
+    {
+        *Box::from_raw_in(target, alloc)
+    }
+}
+
+unsafe impl<T: ?Sized> RawDeref for &T {
+    type Wrapped<D> = Ref<D>;
+
+    type Alloc = ();
+
+    fn wrap<D>(d: D) -> Ref<D> {
+        Ref::new(d)
+    }
+
+    fn into_raw(this: Self) -> (*mut T, ()) {
+        (ptr::from_ref(this).cast_mut(), ())
+    }
+
+    unsafe fn from_raw(target: *mut T, (): ()) -> Self {
+        &*target
+    }
+}
+
+unsafe impl<T: ?Sized> RawDeref for &mut T {
+    type Wrapped<D> = RefMut<D>;
+
+    type Alloc = ();
+
+    fn wrap<D>(d: D) -> RefMut<D> {
+        RefMut::new(d)
+    }
+
+    fn into_raw(this: Self) -> (*mut T, ()) {
+        // Not `this as *const T`, which would go through a shared reborrow
+        // and lose the permission to write.
+        (ptr::from_mut(this), ())
+    }
+
+    unsafe fn from_raw(target: *mut T, (): ()) -> Self {
+        &mut *target
+    }
+}
+
+// Pinned pointers only give out `Ref`s: with a `RefMut` you could
+// `mem::swap` the target out from under its pin.
+
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized> RawDeref for Pin<Box<T>> {
+    type Wrapped<D> = Ref<D>;
+
+    type Alloc = ();
+
+    fn wrap<D>(d: D) -> Ref<D> {
+        Ref::new(d)
+    }
+
+    fn into_raw(this: Self) -> (*mut T, ()) {
+        // We never move the target, and give it back pinned in `from_raw`.
+        (
+            Box::into_raw(unsafe { Pin::into_inner_unchecked(this) }),
+            (),
+        )
+    }
+
+    unsafe fn from_raw(target: *mut T, (): ()) -> Self {
+        Pin::new_unchecked(Box::from_raw(target))
+    }
+}
+
+unsafe impl<T: ?Sized> RawDeref for Pin<&mut T> {
+    type Wrapped<D> = Ref<D>;
+
+    type Alloc = ();
+
+    fn wrap<D>(d: D) -> Ref<D> {
+        Ref::new(d)
+    }
+
+    fn into_raw(this: Self) -> (*mut T, ()) {
+        // We never move the target, and give it back pinned in `from_raw`.
+        (
+            ptr::from_mut(unsafe { Pin::into_inner_unchecked(this) }),
+            (),
+        )
+    }
+
+    unsafe fn from_raw(target: *mut T, (): ()) -> Self {
+        Pin::new_unchecked(&mut *target)
+    }
+}
+
+/// A [`RawDeref`] pointer that a `Ref` to a `Dyn` type can be cloned
+/// through, for `#[dyner(Clone)]` (see [`CloneRef`]). Shared pointers (`&T`,
+/// `Rc<T>`, `Arc<T>`) are copied; the others, which can't be, clone their
+/// target into a new `Box` (pinned, for pinned pointers).
+///
+/// [`CloneRef`]: crate::dyner::CloneRef
+pub trait RawClone: RawDeref {
+    /// The pointer that cloning gives: `Self`, or a `Box` for `&mut T`.
+    type Cloned: RawClone<Target = Self::Target>;
+
+    /// Unsafe: `target` must have been returned from `into_raw`, and not yet
+    /// passed to `from_raw`.
+    unsafe fn clone_raw(target: *mut Self::Target) -> Self::Cloned
+    where
+        Self::Target: Clone;
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> RawClone for Rc<T> {
+    type Cloned = Rc<T>;
+
+    unsafe fn clone_raw(target: *mut T) -> Rc<T> {
+        Rc::increment_strong_count(target);
+        Rc::from_raw(target)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> RawClone for Arc<T> {
+    type Cloned = Arc<T>;
+
+    unsafe fn clone_raw(target: *mut T) -> Arc<T> {
+        Arc::increment_strong_count(target);
+        Arc::from_raw(target)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> RawClone for Box<T> {
+    type Cloned = Box<T>;
+
+    unsafe fn clone_raw(target: *mut T) -> Box<T>
+    where
+        T: Clone,
+    {
+        Box::new((*target).clone())
+    }
+}
+
+impl<T: ?Sized> RawClone for &T {
+    type Cloned = Self;
+
+    unsafe fn clone_raw(target: *mut T) -> Self {
+        &*target
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> RawClone for &mut T {
+    type Cloned = Box<T>;
+
+    unsafe fn clone_raw(target: *mut T) -> Box<T>
+    where
+        T: Clone,
+    {
+        Box::new((*target).clone())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> RawClone for Pin<Box<T>> {
+    type Cloned = Pin<Box<T>>;
+
+    unsafe fn clone_raw(target: *mut T) -> Pin<Box<T>>
+    where
+        T: Clone,
+    {
+        Box::pin((*target).clone())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> RawClone for Pin<&mut T> {
+    type Cloned = Pin<Box<T>>;
+
+    unsafe fn clone_raw(target: *mut T) -> Pin<Box<T>>
+    where
+        T: Clone,
+    {
+        Box::pin((*target).clone())
+    }
+}
+
+/// Remember<P> is a bit of a funky type. The idea is that you have a pointer
+/// type like `Rc<T>` and you are going to convert it to a `*const T`; but
+/// you'd like to remember in the type what the real pointer type is (i.e,
+/// that this `*const` is actually an `Rc`).
+///
+/// `Remember<P>` is always `Sized`, even if `P::Target` isn't (e.g., `[T]`
+/// or `str`), so it can always be unsized to a `dyn ErasedFoo`. It doesn't
+/// drop the `P` itself; [`DynPtr`] does that through [`ErasedDrop`].
+///
+/// A `Remember<&T>` mustn't give out `&mut T`, so constructing one (and
+/// getting mutable access to its target) is unsafe:
+///
+/// ```compile_fail
+/// use dyner::dyn_ptr::Remember;
+///
+/// let x = 5;
+/// let mut remember = Remember::new(&x);
+/// *remember.target_mut() = 6;
+/// ```
+pub struct Remember<P: RawDeref> {
+    raw: *mut P::Target,
+    /// Taken by `drop_me` or `take_target`, whichever is called.
+    alloc: ManuallyDrop<P::Alloc>,
+    _marker: PhantomData<P>,
+}
+
+impl<P: RawDeref> Remember<P> {
+    /// Unsafe: `&mut` access to the `Remember` (and so `target_mut`) must
+    /// only be given out if `P` gives out `&mut P::Target`, as
+    /// [`RawDeref::Wrapped`] does for a `Dyn` type.
+    pub unsafe fn new(value: P) -> Self {
+        let (raw, alloc) = RawDeref::into_raw(value);
+        Remember {
+            raw,
+            alloc: ManuallyDrop::new(alloc),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn target(&self) -> &P::Target {
+        unsafe { &*self.raw }
+    }
+
+    /// Unsafe: `P` must give out `&mut P::Target` (see [`Remember::new`]).
+    pub unsafe fn target_mut(&mut self) -> &mut P::Target {
+        &mut *self.raw
+    }
+
+    /// Moves the target out, for calling a `self` method.
+    ///
+    /// Unsafe:
+    ///
+    /// * `P::OWNED` must be true (so `P` is a `Box`)
+    /// * neither this nor `drop_me` may be called afterwards
+    pub unsafe fn take_target(&mut self) -> P::Target
+    where
+        P::Target: Sized,
+    {
+        P::take_raw(self.raw, ManuallyDrop::take(&mut self.alloc))
+    }
+}
+
+impl<P: RawClone> Remember<P> {
+    /// Clones the pointer, for cloning a `Ref` (see [`RawClone`]).
+    pub fn clone_ptr(&self) -> Remember<P::Cloned>
+    where
+        P::Target: Clone,
+    {
+        // The clone of a shared pointer is only given out in a `Ref`.
+        unsafe { Remember::new(P::clone_raw(self.raw)) }
+    }
+
+    /// Clones the target into a new `Box`, for cloning a `Dyn` type, which
+    /// might give out `&mut` access to it.
+    #[cfg(feature = "alloc")]
+    pub fn clone_boxed(&self) -> Remember<Box<P::Target>>
+    where
+        P::Target: Clone,
+    {
+        unsafe { Remember::new(Box::new(self.target().clone())) }
+    }
+}
+
+/// Supertrait of every erased trait, which drops the pointer that the
+/// object was constructed from.
+pub trait ErasedDrop {
+    /// Takes a raw pointer, rather than `&self`, because `self` must not be
+    /// used afterwards: a reference would claim it stays valid for the whole
+    /// call.
+    ///
+    /// Unsafe: must be called at most once, with a pointer that is valid for
+    /// writes, after which `*self` must not be used.
+    unsafe fn drop_me(self: *mut Self);
+
+    /// True if we were constructed from a pointer that owns its target (a
+    /// `Box`), so that `self` methods can be called.
+    fn is_owned(&self) -> bool;
+}
+
+impl<P: RawDeref> ErasedDrop for Remember<P> {
+    unsafe fn drop_me(self: *mut Self) {
+        let _value: P = P::from_raw((*self).raw, ManuallyDrop::take(&mut (*self).alloc));
+    }
+
+    fn is_owned(&self) -> bool {
+        P::OWNED
+    }
+}
+
+/// The pointer stored in a `Dyn` type: a `Remember<P>`, stored inline,
+/// along with the vtable of the `dyn ErasedFoo` it was unsized to. Dropping
+/// the `DynPtr` drops the `P`.
+///
+/// A bare `DynFoo` gives out `&mut` access to its target, so it can't be
+/// built from a `DynPtr` over a `&T` in safe code:
+///
+/// ```compile_fail
+/// use dyner::dyn_ptr::{DynPtr, Remember};
+///
+/// #[dyner::dyner]
+/// trait Incr {
+///     fn incr(&mut self);
+/// }
+///
+/// impl Incr for u32 {
+///     fn incr(&mut self) {
+///         *self += 1;
+///     }
+/// }
+///
+/// let x = 5_u32;
+/// let ptr = DynPtr::new(Remember::new(&x));
+/// DynIncr::__from_dyn_ptr(ptr).incr();
+/// ```
+pub struct DynPtr<T: ?Sized + ErasedDrop> {
+    /// The `Remember<P>`, which is one word, or two if `P::Target` is
+    /// unsized or `P` is a `Box<T, A>` with a non-zero-sized `A`.
+    remember: MaybeUninit<[*const (); 2]>,
+    metadata: <T as Pointee>::Metadata,
+}
+
+impl<T: ?Sized + ErasedDrop> DynPtr<T> {
+    /// Unsafe: as for [`Remember::new`], `&mut` access to the `DynPtr` must
+    /// only be given out if `P` gives out `&mut P::Target`.
+    pub unsafe fn new<P>(remember: Remember<P>) -> Self
+    where
+        P: RawDeref,
+        Remember<P>: Unsize<T>,
+    {
+        const {
+            assert!(size_of::<Remember<P>>() <= size_of::<[*const (); 2]>());
+            assert!(align_of::<Remember<P>>() <= align_of::<[*const (); 2]>());
+        }
+        let mut storage = MaybeUninit::<[*const (); 2]>::uninit();
+        let raw: *mut Remember<P> = storage.as_mut_ptr().cast();
+        raw.write(remember);
+        let erased: *mut T = raw;
+        DynPtr {
+            remember: storage,
+            metadata: ptr::metadata(erased),
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
+        ptr::from_raw_parts_mut(self.remember.as_ptr() as *mut (), self.metadata)
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        ptr::from_raw_parts_mut(self.remember.as_mut_ptr(), self.metadata)
+    }
+
+    /// True if this was constructed from a `Box`.
+    pub fn is_owned(&self) -> bool {
+        unsafe { ErasedDrop::is_owned(&*self.as_ptr()) }
+    }
+
+    /// The same pointer, erased to a supertrait (e.g., from
+    /// `dyn ErasedFoo` to `dyn ErasedBar`, for `DynFoo::into_bar`).
+    pub fn upcast<U>(self) -> DynPtr<U>
+    where
+        U: ?Sized + ErasedDrop,
+        T: Unsize<U>,
+    {
+        let this = ManuallyDrop::new(self);
+        let erased: *mut U = this.as_ptr();
+        DynPtr {
+            remember: this.remember,
+            metadata: ptr::metadata(erased),
+        }
+    }
+
+    /// Like `upcast`, but copies the pointer rather than taking it (e.g.,
+    /// for `DynFoo::as_bar`).
+    ///
+    /// Unsafe: we still own the pointer, so the result must not be dropped
+    /// (as in [`Ref::without_drop`]), must not outlive us, and must only be
+    /// given out behind `&`.
+    pub unsafe fn upcast_ref<U>(&self) -> DynPtr<U>
+    where
+        U: ?Sized + ErasedDrop,
+        T: Unsize<U>,
+    {
+        let erased: *mut U = self.as_ptr();
+        DynPtr {
+            remember: self.remember,
+            metadata: ptr::metadata(erased),
+        }
+    }
+
+    /// For calling `self` methods, which take the target out of the `Box`
+    /// we were constructed from: so we mustn't drop it again. Panics if we
+    /// weren't constructed from a `Box`: `Ref` and `RefMut` don't let you
+    /// move out, but you could still `mem::swap` a borrowed `DynFoo` into an
+    /// owned one.
+    pub fn into_owned(self) -> ManuallyDrop<Self> {
+        assert!(
+            self.is_owned(),
+            "cannot call a `self` method on a borrowed object"
+        );
+        ManuallyDrop::new(self)
+    }
+}
+
+impl<T: ?Sized + ErasedDrop> Drop for DynPtr<T> {
+    fn drop(&mut self) {
+        unsafe { ErasedDrop::drop_me(self.as_mut_ptr()) }
+    }
+}
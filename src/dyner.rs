@@ -1,16 +1,62 @@
-use std::{future::Future, mem::MaybeUninit, pin::Pin};
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::{
+    cell::{Cell, UnsafeCell},
+    future::Future,
+    marker::PhantomPinned,
+    mem::{ManuallyDrop, MaybeUninit},
+    pin::Pin,
+};
+#[cfg(feature = "alloc")]
+use core::{
+    mem::{align_of, size_of},
+    ptr::{self, DynMetadata},
+};
 
+/// Newtype that permits shared (`&T`) access, but nothing else.
+///
+/// In particular, you can't move out of a `Ref`, so a borrowed `DynFoo`
+/// can't be passed to a by-value `self` method:
+///
+/// ```compile_fail
+/// #[dyner::dyner]
+/// trait Finish {
+///     fn finish(self) -> u32;
+/// }
+///
+/// impl Finish for u32 {
+///     fn finish(self) -> u32 {
+///         self
+///     }
+/// }
+///
+/// DynFinish::from_ptr(&22).finish();
+/// ```
 pub struct Ref<T> {
-    t: T,
+    t: ManuallyDrop<T>,
+    /// False if `t` shares a pointer that something else owns.
+    drop: bool,
 }
 
 impl<T> Ref<T> {
     pub fn new(t: T) -> Self {
-        Self { t }
+        Self {
+            t: ManuallyDrop::new(t),
+            drop: true,
+        }
+    }
+
+    /// Like `new`, but never drops `t`: for a `Dyn` type that shares the
+    /// pointer of one that still owns it (see `DynPtr::upcast_ref`).
+    pub fn without_drop(t: T) -> Self {
+        Self {
+            t: ManuallyDrop::new(t),
+            drop: false,
+        }
     }
 }
 
-impl<T> std::ops::Deref for Ref<T> {
+impl<T> core::ops::Deref for Ref<T> {
     type Target = T;
 
     fn deref(&self) -> &T {
@@ -18,8 +64,68 @@ impl<T> std::ops::Deref for Ref<T> {
     }
 }
 
+impl<T> Drop for Ref<T> {
+    fn drop(&mut self) {
+        if self.drop {
+            unsafe { ManuallyDrop::drop(&mut self.t) }
+        }
+    }
+}
+
+/// Implemented by `Dyn` types for `#[dyner(Clone)]`, so that cloning a
+/// `Ref<DynFoo>` copies the pointer it was constructed from (e.g., the `&T`
+/// or the `Rc<T>`; see [`RawClone`]) rather than its target. Cloning the
+/// `DynFoo` itself clones its target into a new `Box`.
+///
+/// [`RawClone`]: crate::dyn_ptr::RawClone
+pub trait CloneRef {
+    /// Unsafe: the result may share its target with `self`, so it must only
+    /// be given out in a `Ref`.
+    unsafe fn clone_ref(&self) -> Self;
+}
+
+impl<T: CloneRef> Clone for Ref<T> {
+    fn clone(&self) -> Self {
+        Ref::new(unsafe { self.t.clone_ref() })
+    }
+}
+
 /// Newtype that permits borrowed (`&mut T`) or shared (`&T`) access,
 /// but nothing else.
+///
+/// As with [`Ref`], you can't move out of a `RefMut`:
+///
+/// ```compile_fail
+/// #[dyner::dyner]
+/// trait Finish {
+///     fn finish(self) -> u32;
+/// }
+///
+/// impl Finish for u32 {
+///     fn finish(self) -> u32 {
+///         self
+///     }
+/// }
+///
+/// DynFinish::from_ptr(&mut 22).finish();
+/// ```
+///
+/// Whereas an owned object is fine:
+///
+/// ```
+/// # #[dyner::dyner]
+/// # trait Finish {
+/// #     fn finish(self) -> u32;
+/// # }
+/// #
+/// # impl Finish for u32 {
+/// #     fn finish(self) -> u32 {
+/// #         self
+/// #     }
+/// # }
+/// # #[cfg(feature = "alloc")]
+/// assert_eq!(DynFinish::new(22).finish(), 22);
+/// ```
 pub struct RefMut<T> {
     t: T,
 }
@@ -30,7 +136,7 @@ impl<T> RefMut<T> {
     }
 }
 
-impl<T> std::ops::Deref for RefMut<T> {
+impl<T> core::ops::Deref for RefMut<T> {
     type Target = T;
 
     fn deref(&self) -> &T {
@@ -38,36 +144,159 @@ impl<T> std::ops::Deref for RefMut<T> {
     }
 }
 
-impl<T> std::ops::DerefMut for RefMut<T> {
+impl<T> core::ops::DerefMut for RefMut<T> {
     fn deref_mut(&mut self) -> &mut T {
         &mut self.t
     }
 }
 
+/// Raw pointer to an erased trait object (e.g., `dyn DynAsyncIterTrait`)
+/// that also records whether the object is owned -- i.e., whether it came
+/// from `Box::into_raw` and must be freed when the `Dyn` type is dropped.
+///
+/// Ownership is a separate field, rather than a bit stolen from the
+/// pointer, so this works whatever the layout of `*mut T` and the alignment
+/// of the object (a `u8`, or a zero-sized type, can live at an odd address).
+pub struct FatPtr<T: ?Sized> {
+    raw: *mut T,
+    owned: bool,
+}
+
+impl<T: ?Sized> Copy for FatPtr<T> {}
+
+impl<T: ?Sized> Clone for FatPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> FatPtr<T> {
+    /// A pointer to a borrowed object.
+    pub fn new(raw: *mut T) -> Self {
+        FatPtr { raw, owned: false }
+    }
+
+    /// A pointer to an object from `Box::into_raw`.
+    pub fn owned(raw: *mut T) -> Self {
+        FatPtr { raw, owned: true }
+    }
+
+    pub fn as_ptr(self) -> *mut T {
+        self.raw
+    }
+
+    pub fn is_owned(self) -> bool {
+        self.owned
+    }
+}
+
+/// Space for one future of type `F`, which tracks whether it is occupied
+/// so that each future stored in it is dropped exactly once: by its
+/// [`InlineFuture`], or, if that was forgotten, when the slot is refilled
+/// through `&mut` or dropped.
+///
+/// Futures are polled in place, so a slot can only be filled once it's
+/// pinned: otherwise the slot, along with a future whose `InlineFuture` was
+/// forgotten, could be moved.
+pub struct InlineSlot<F> {
+    in_use: Cell<bool>,
+    future: UnsafeCell<MaybeUninit<F>>,
+    _pinned: PhantomPinned,
+}
+
+impl<F> InlineSlot<F> {
+    pub fn new() -> Self {
+        Self {
+            in_use: Cell::new(false),
+            future: UnsafeCell::new(MaybeUninit::uninit()),
+            _pinned: PhantomPinned,
+        }
+    }
+
+    pub fn is_free(&self) -> bool {
+        !self.in_use.get()
+    }
+
+    /// Drops the future left in the slot by a forgotten `InlineFuture`, if
+    /// any. As we have `&mut self`, no `InlineFuture` can still be using it.
+    pub fn clear(self: Pin<&mut Self>) {
+        // Dropping the future in place is fine by the pinning guarantee.
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.in_use.replace(false) {
+            unsafe { this.future.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+impl<F> Default for InlineSlot<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> Drop for InlineSlot<F> {
+    fn drop(&mut self) {
+        // Okay because we're never used again.
+        unsafe { Pin::new_unchecked(self) }.clear();
+    }
+}
+
+impl<F: Future> InlineSlot<F> {
+    /// Moves `future` into the slot, or gives it back if the slot is still
+    /// holding an earlier one.
+    pub fn try_fill(self: Pin<&Self>, future: F) -> Result<InlineFuture<'_, F::Output>, F> {
+        let this = self.get_ref();
+        if this.in_use.replace(true) {
+            return Err(future);
+        }
+        let slot = unsafe { &mut *this.future.get() };
+        Ok(InlineFuture {
+            future: slot.write(future),
+            in_use: Some(&this.in_use),
+        })
+    }
+
+    /// Moves `future` into the slot, first dropping whatever was left there.
+    pub fn fill(mut self: Pin<&mut Self>, future: F) -> InlineFuture<'_, F::Output> {
+        self.as_mut().clear();
+        match self.into_ref().try_fill(future) {
+            Ok(future) => future,
+            Err(_) => unreachable!("slot was just cleared"),
+        }
+    }
+}
+
+/// A future stored in an [`InlineSlot`], which it frees when dropped; or,
+/// if there was no free slot, a boxed future.
 pub struct InlineFuture<'me, Output> {
-    future: &'me mut dyn Future<Output = Output>,
+    future: *mut (dyn Future<Output = Output> + 'me),
+    /// `None` if the future is boxed.
+    in_use: Option<&'me Cell<bool>>,
 }
 
+#[cfg(feature = "alloc")]
 impl<'me, Output> InlineFuture<'me, Output> {
-    /// Safe:
-    ///
-    /// * `*future` belongs to us for duration of `'me`
-    ///
-    /// Unsafe:
-    ///
-    /// * `*future` must be initialized
-    /// * `*future` must not be used again without having been reinitialized (which must occur after `'me` ends)
-    pub unsafe fn new(future: &'me mut MaybeUninit<impl Future<Output = Output>>) -> Self {
+    pub fn boxed(future: impl Future<Output = Output> + 'me) -> Self {
+        let future: Box<dyn Future<Output = Output> + 'me> = Box::new(future);
         Self {
-            future: future.assume_init_mut(),
+            future: Box::into_raw(future),
+            in_use: None,
         }
     }
 }
 
 impl<'me, Output> Drop for InlineFuture<'me, Output> {
     fn drop(&mut self) {
-        unsafe {
-            std::ptr::drop_in_place(self.future);
+        match self.in_use {
+            Some(in_use) => {
+                unsafe { core::ptr::drop_in_place(self.future) };
+                in_use.set(false);
+            }
+            // Only `boxed` leaves `in_use` empty.
+            #[cfg(feature = "alloc")]
+            None => drop(unsafe { Box::from_raw(self.future) }),
+            #[cfg(not(feature = "alloc"))]
+            None => unreachable!(),
         }
     }
 }
@@ -77,60 +306,133 @@ impl<'me, Output> Future for InlineFuture<'me, Output> {
 
     fn poll(
         self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        unsafe {
-            let mu: &mut dyn Future<Output = Output> = &mut *self.get_unchecked_mut().future;
-            let mut mu_pin: Pin<&mut dyn Future<Output = Output>> = Pin::new_unchecked(mu);
-            <Pin<&mut dyn Future<Output = Output>> as Future>::poll(Pin::new(&mut mu_pin), cx)
-        }
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        // The future stays put until we drop it: its slot is pinned, and a
+        // box never moves its contents.
+        let future = self.future;
+        unsafe { Pin::new_unchecked(&mut *future).poll(cx) }
     }
 }
 
-pub struct InlineRefCellFuture<'me, Output> {
-    future: std::cell::RefMut<'me, dyn Future<Output = Output> + 'me>,
+/// An owned, erased future, stored inline if it fits in `N` bytes (with
+/// alignment of at most 16), and boxed otherwise.
+///
+/// The future is polled in place, so it's only pinned once the
+/// `SmallFuture` is; until then, it may be moved along with it.
+#[cfg(feature = "alloc")]
+pub struct SmallFuture<'me, Output, const N: usize> {
+    /// In an `UnsafeCell` as the future may hold pointers into itself,
+    /// which even a `&self` would otherwise claim are frozen.
+    buffer: UnsafeCell<SmallBuffer<N>>,
+    /// Null if the future is stored in `buffer`.
+    heap: *mut (),
+    metadata: DynMetadata<dyn Future<Output = Output> + 'me>,
+    _pinned: PhantomPinned,
 }
 
-impl<'me, Output> InlineRefCellFuture<'me, Output> {
-    /// Safe:
-    ///
-    /// * `*future` belongs to us for duration of `'me`
-    ///
-    /// Unsafe:
-    ///
-    /// * `*future` must be initialized
-    /// * `*future` must not be used again without having been reinitialized (which must occur after `'me` ends)
-    pub unsafe fn new(
-        future: std::cell::RefMut<'me, MaybeUninit<impl Future<Output = Output>>>,
-    ) -> Self {
-        let future = std::cell::RefMut::map(
-            future,
-            |f: &mut _| -> &mut (dyn Future<Output = Output> + 'me) { f.assume_init_mut() },
-        );
+#[cfg(feature = "alloc")]
+#[repr(C, align(16))]
+struct SmallBuffer<const N: usize>([MaybeUninit<u8>; N]);
+
+#[cfg(feature = "alloc")]
+impl<'me, Output, const N: usize> SmallFuture<'me, Output, N> {
+    pub fn new<F>(future: F) -> Self
+    where
+        F: Future<Output = Output> + 'me,
+    {
+        let erased: *mut (dyn Future<Output = Output> + 'me) = ptr::null_mut::<F>();
+        let metadata = ptr::metadata(erased);
+        let buffer = UnsafeCell::new(SmallBuffer([MaybeUninit::uninit(); N]));
+        let heap = if Self::fits::<F>() {
+            unsafe { buffer.get().cast::<F>().write(future) };
+            ptr::null_mut()
+        } else {
+            Box::into_raw(Box::new(future)).cast()
+        };
+        SmallFuture {
+            buffer,
+            heap,
+            metadata,
+            _pinned: PhantomPinned,
+        }
+    }
+
+    /// True if a future of type `F` would be stored inline.
+    pub const fn fits<F>() -> bool {
+        size_of::<F>() <= N && align_of::<F>() <= align_of::<SmallBuffer<N>>()
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.heap.is_null()
+    }
 
-        Self { future }
+    fn as_mut_ptr(&mut self) -> *mut (dyn Future<Output = Output> + 'me) {
+        let data = if self.is_inline() {
+            self.buffer.get().cast()
+        } else {
+            self.heap
+        };
+        ptr::from_raw_parts_mut(data, self.metadata)
     }
 }
 
-impl<'me, Output> Drop for InlineRefCellFuture<'me, Output> {
+#[cfg(feature = "alloc")]
+impl<'me, Output, const N: usize> Drop for SmallFuture<'me, Output, N> {
     fn drop(&mut self) {
-        unsafe {
-            std::ptr::drop_in_place(&mut *self.future);
+        let future = self.as_mut_ptr();
+        if self.is_inline() {
+            unsafe { ptr::drop_in_place(future) }
+        } else {
+            drop(unsafe { Box::from_raw(future) })
         }
     }
 }
 
-impl<'me, Output> Future for InlineRefCellFuture<'me, Output> {
+#[cfg(feature = "alloc")]
+impl<'me, Output, const N: usize> Future for SmallFuture<'me, Output, N> {
     type Output = Output;
 
     fn poll(
         self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        unsafe {
-            let mu: &mut dyn Future<Output = Output> = &mut *self.get_unchecked_mut().future;
-            let mut mu_pin: Pin<&mut dyn Future<Output = Output>> = Pin::new_unchecked(mu);
-            <Pin<&mut dyn Future<Output = Output>> as Future>::poll(Pin::new(&mut mu_pin), cx)
-        }
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        // The future is structurally pinned: we never move it out, and drop
+        // it in place.
+        let future = unsafe { self.get_unchecked_mut() }.as_mut_ptr();
+        unsafe { Pin::new_unchecked(&mut *future).poll(cx) }
     }
 }
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn small_future_inline() {
+    let x = 22_u64;
+    let future = SmallFuture::<'_, u64, 16>::new(async move { x });
+    assert!(future.is_inline());
+    assert_eq!(future.await, 22);
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn small_future_boxed() {
+    let xs = [22_u64; 4];
+    let future = SmallFuture::<'_, u64, 16>::new(async move { xs.iter().sum() });
+    assert!(!future.is_inline());
+    assert_eq!(future.await, 88);
+}
+
+#[cfg(all(test, feature = "std"))]
+#[tokio::test]
+async fn small_future_over_aligned() {
+    #[repr(align(32))]
+    struct Aligned(u8);
+
+    let x = Aligned(22);
+    let future = SmallFuture::<'_, u8, 64>::new(async move {
+        let x: Aligned = x;
+        x.0
+    });
+    assert!(!future.is_inline());
+    assert_eq!(future.await, 22);
+}
@@ -1,8 +1,5 @@
-use std::{
-    ops::{Deref, DerefMut},
-    rc::Rc,
-};
-use crate::dyner::{Ref, RefMut};
+use crate::dyn_ptr::{DynPtr, ErasedDrop, RawDeref, Remember};
+use core::ops::Deref;
 
 trait Len {
     fn len(&self) -> usize;
@@ -22,99 +19,22 @@ trait Len {
 //
 // and it should know to transmute the `*const` to an `Rc<T>` and drop it
 
-trait ErasedLen {
+trait ErasedLen: ErasedDrop {
     fn len(&self) -> usize;
     fn modify(&mut self);
-
-    fn drop_me(&self);
-}
-
-trait RawDeref: Deref {
-    fn into_raw(this: Self) -> *const Self::Target;
-
-    // Unsafe: `target` must have been returned from `into_raw`
-    unsafe fn from_raw(target: *const Self::Target) -> Self;
-}
-
-impl<T> RawDeref for Rc<T> {
-    fn into_raw(this: Self) -> *const T {
-        Rc::into_raw(this)
-    }
-
-    unsafe fn from_raw(target: *const T) -> Self {
-        Rc::from_raw(target)
-    }
-}
-
-impl<T> RawDeref for Box<T> {
-    fn into_raw(this: Self) -> *const T {
-        Box::into_raw(this)
-    }
-
-    unsafe fn from_raw(target: *const T) -> Self {
-        Box::from_raw(target as *mut T)
-    }
-}
-
-impl<T> RawDeref for &T {
-    fn into_raw(this: Self) -> *const T {
-        this
-    }
-
-    unsafe fn from_raw(target: *const T) -> Self {
-        &*target
-    }
-}
-
-impl<T> RawDeref for &mut T {
-    fn into_raw(this: Self) -> *const T {
-        this
-    }
-
-    unsafe fn from_raw(target: *const T) -> Self {
-        // Cast to *mut is okay because this method's invariant is that target
-        // will always come from Self::into_raw.
-        &mut *(target as *mut T)
-    }
-}
-
-/// Remember<T> is a bit of a funky type. The idea is that you have a pointer
-/// type like `Rc<T>` and you are going to transmute it to a `*const U`; but
-/// you'd like to remember in the type of U what the real pointer type is (i.e,
-/// that this `*const` is actually an `Rc`).
-///
-/// takes a pointer type T = Ptr<U> and
-///
-#[repr(transparent)]
-struct Remember<T: RawDeref> {
-    t: T::Target,
-}
-
-impl<T: RawDeref> Remember<T> {
-    pub fn new(value: T) -> *const Self {
-        let ptr: *const T::Target = RawDeref::into_raw(value);
-        ptr as *const Self
-    }
 }
 
-impl<T> ErasedLen for Remember<T>
+impl<P> ErasedLen for Remember<P>
 where
-    T: RawDeref,
-    T::Target: Len,
+    P: RawDeref,
+    P::Target: Len,
 {
     fn len(&self) -> usize {
-        Len::len(&self.t)
+        Len::len(self.target())
     }
 
     fn modify(&mut self) {
-        Len::modify(&mut self.t)
-    }
-
-    // FIXME: This is probably UB, and should be *const self
-    fn drop_me(&self) {
-        unsafe {
-            let _value: T = T::from_raw(std::ptr::addr_of!(self.t));
-        }
+        Len::modify(unsafe { self.target_mut() })
     }
 }
 
@@ -122,68 +42,100 @@ where
 // &Foo was shorthand for Deref<Target: Foo>
 
 struct DynLen<'data> {
-    ptr: *mut (dyn ErasedLen + 'data),
+    ptr: DynPtr<dyn ErasedLen + 'data>,
 }
 
 impl<'data> DynLen<'data> {
     #[allow(dead_code)]
-    fn from_ref<P>(value: P) -> Ref<DynLen<'data>>
+    fn from_ptr<P>(value: P) -> P::Wrapped<DynLen<'data>>
     where
         P: RawDeref + 'data,
-        <P as Deref>::Target: Len + Sized,
+        <P as Deref>::Target: Len,
     {
-        // Cast to *mut is okay because we're guarding everything behind Ref.
-        let v: *mut Remember<P> = Remember::new(value) as _;
-        let v: *mut (dyn ErasedLen + 'data) = v;
-        Ref::new(DynLen { ptr: v })
-    }
-
-    #[allow(dead_code)]
-    fn from_mut<P>(value: P) -> RefMut<DynLen<'data>>
-    where
-        P: RawDeref + DerefMut + 'data,
-        <P as Deref>::Target: Len + Sized,
-    {
-        // Cast to *mut is okay because P: DerefMut.
-        let v: *mut Remember<P> = Remember::new(value) as _;
-        let v: *mut (dyn ErasedLen + 'data) = v;
-        RefMut::new(DynLen { ptr: v })
+        P::wrap(DynLen {
+            ptr: unsafe { DynPtr::new(Remember::new(value)) },
+        })
     }
 }
 
 impl Len for DynLen<'_> {
     fn len(&self) -> usize {
-        unsafe { ErasedLen::len(&*self.ptr) }
+        unsafe { ErasedLen::len(&*self.ptr.as_ptr()) }
     }
 
     fn modify(&mut self) {
-        unsafe { ErasedLen::modify(&mut *self.ptr) }
+        unsafe { ErasedLen::modify(&mut *self.ptr.as_mut_ptr()) }
     }
 }
 
-impl Drop for DynLen<'_> {
-    fn drop(&mut self) {
-        unsafe { ErasedLen::drop_me(&*self.ptr) }
+impl<T: Default, const N: usize> Len for [T; N] {
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    fn modify(&mut self) {
+        <[T] as Len>::modify(self)
     }
 }
 
-// FIXME: Get this working with [T].
-// The unsized coercion from Remember<P> above doesn't support already-unsized targets.
-impl<T: Default, const N: usize> Len for [T; N] {
+impl<T: Default> Len for [T] {
     fn len(&self) -> usize {
         <[T]>::len(self)
     }
 
     fn modify(&mut self) {
-        if self.len() != 0 {
+        if !self.is_empty() {
             self[0] = Default::default();
         }
     }
 }
 
+impl Len for str {
+    fn len(&self) -> usize {
+        str::len(self)
+    }
+
+    fn modify(&mut self) {
+        self.make_ascii_uppercase()
+    }
+}
+
+// Borrowed objects need neither `alloc` nor `std`, so these also run under
+// `cargo test --lib --no-default-features`.
 #[cfg(test)]
+mod borrowed_test {
+    use super::*;
+
+    #[test]
+    fn len_from_ref() {
+        let items = [1, 2, 3];
+        let dyn_items = DynLen::from_ptr(&items);
+        assert_eq!(3, dyn_items.len());
+        assert!(!dyn_items.ptr.is_owned());
+    }
+
+    #[test]
+    fn len_from_mut() {
+        let mut items = [1, 2, 3];
+        {
+            let mut dyn_items = DynLen::from_ptr(&mut items);
+            dyn_items.modify();
+            assert_eq!(3, dyn_items.len());
+        }
+        assert_eq!([0, 2, 3], items);
+
+        let mut text = *b"abc";
+        let text = core::str::from_utf8_mut(&mut text).unwrap();
+        DynLen::from_ptr(&mut *text).modify();
+        assert_eq!("ABC", text);
+    }
+}
+
+// These exercise the raw pointer juggling in `dyn_ptr`, so they should also
+// pass under `cargo miri test dynerx`.
+#[cfg(all(test, feature = "std"))]
 mod test {
-    use std::cell::RefCell;
+    use std::{cell::RefCell, pin::Pin, rc::Rc, sync::Arc};
 
     use super::*;
 
@@ -212,9 +164,9 @@ mod test {
     #[test]
     fn test_len() {
         let mut local_items = [1, 2, 3];
-        assert_eq!(3, get_len(&*DynLen::from_ref(&local_items)));
+        assert_eq!(3, get_len(&*DynLen::from_ptr(&local_items)));
         {
-            let mut dyn_mut_items = DynLen::from_mut(&mut local_items);
+            let mut dyn_mut_items = DynLen::from_ptr(&mut local_items);
             dyn_mut_items.modify();
             assert_eq!(3, dyn_mut_items.len());
         }
@@ -223,9 +175,9 @@ mod test {
         let drop_counter = DropCounter::new();
         let box_items = Box::new([Some(drop_counter.clone()), None, Some(drop_counter.clone())]);
         {
-            let mut dyn_items = DynLen::from_mut(box_items);
+            let mut dyn_items = DynLen::from_ptr(box_items);
             assert_eq!(0, drop_counter.count());
-            assert_eq!(3, get_len(&*dyn_items));
+            assert_eq!(3, get_len(&dyn_items));
             dyn_items.modify(); // drops the first element
             assert_eq!(1, drop_counter.count());
         }
@@ -235,13 +187,152 @@ mod test {
         let rc_items = Rc::new([None, None, Some(drop_counter.clone())]);
         let rc_items2 = Rc::clone(&rc_items);
         {
-            let dyn_items = DynLen::from_ref(rc_items);
+            let dyn_items = DynLen::from_ptr(rc_items);
             assert_eq!(0, drop_counter.count());
             assert_eq!(3, get_len(&*dyn_items));
             // dyn_items.modify(); <-- does not compile
         }
         assert_eq!(0, drop_counter.count());
-        assert_eq!(3, get_len(&*DynLen::from_ref(rc_items2)));
+        assert_eq!(3, get_len(&*DynLen::from_ptr(rc_items2)));
         assert_eq!(1, drop_counter.count());
     }
+
+    #[test]
+    // Only `Arc`'s `RawDeref` impl is under test, on a single thread.
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn test_len_arc() {
+        let drop_counter = DropCounter::new();
+        let arc_items = Arc::new([None, None, Some(drop_counter.clone())]);
+        let arc_items2 = Arc::clone(&arc_items);
+        {
+            let dyn_items = DynLen::from_ptr(arc_items);
+            assert_eq!(0, drop_counter.count());
+            assert_eq!(3, get_len(&*dyn_items));
+        }
+        assert_eq!(0, drop_counter.count());
+        assert_eq!(3, get_len(&*DynLen::from_ptr(arc_items2)));
+        assert_eq!(1, drop_counter.count());
+    }
+
+    #[test]
+    fn test_len_pin_box() {
+        let drop_counter = DropCounter::new();
+        let pinned_items = Box::pin([Some(drop_counter.clone()), None, Some(drop_counter.clone())]);
+        {
+            let dyn_items = DynLen::from_ptr(pinned_items);
+            assert_eq!(0, drop_counter.count());
+            assert_eq!(3, get_len(&*dyn_items));
+            // dyn_items.modify(); <-- does not compile
+        }
+        assert_eq!(2, drop_counter.count());
+    }
+
+    #[test]
+    fn test_len_pin_mut() {
+        let drop_counter = DropCounter::new();
+        let mut local_items = [Some(drop_counter.clone()), None, None];
+        {
+            let dyn_items = DynLen::from_ptr(Pin::new(&mut local_items));
+            assert_eq!(3, get_len(&*dyn_items));
+        }
+        assert_eq!(0, drop_counter.count());
+        drop(local_items);
+        assert_eq!(1, drop_counter.count());
+    }
+
+    #[test]
+    fn test_len_box_slice() {
+        let drop_counter = DropCounter::new();
+        let box_items: Box<[_]> =
+            vec![Some(drop_counter.clone()), None, Some(drop_counter.clone())].into();
+        {
+            let mut dyn_items = DynLen::from_ptr(box_items);
+            assert_eq!(0, drop_counter.count());
+            assert_eq!(3, get_len(&dyn_items));
+            dyn_items.modify(); // drops the first element
+            assert_eq!(1, drop_counter.count());
+        }
+        assert_eq!(2, drop_counter.count());
+    }
+
+    #[test]
+    fn test_len_rc_slice() {
+        let drop_counter = DropCounter::new();
+        let rc_items: Rc<[_]> = Rc::new([None, None, Some(drop_counter.clone())]);
+        let rc_items2 = Rc::clone(&rc_items);
+        {
+            let dyn_items = DynLen::from_ptr(rc_items);
+            assert_eq!(3, get_len(&*dyn_items));
+        }
+        assert_eq!(0, drop_counter.count());
+        assert_eq!(3, get_len(&*DynLen::from_ptr(rc_items2)));
+        assert_eq!(1, drop_counter.count());
+    }
+
+    #[test]
+    fn test_len_str() {
+        let mut string = String::from("hello, world");
+        // Odd addresses are fine too.
+        assert_eq!(11, get_len(&*DynLen::from_ptr(&string[1..])));
+        DynLen::from_ptr(&mut string[7..]).modify();
+        assert_eq!("hello, WORLD", string);
+
+        let arc_str: Arc<str> = Arc::from("hello");
+        let arc_str2 = Arc::clone(&arc_str);
+        assert_eq!(5, get_len(&*DynLen::from_ptr(arc_str)));
+        assert_eq!(1, Arc::strong_count(&arc_str2));
+    }
+
+    #[test]
+    fn test_drop_box() {
+        let drop_counter = DropCounter::new();
+        let mut dyn_items = DynLen::from_ptr(Box::new([Some(drop_counter.clone())]));
+        dyn_items.modify();
+        assert_eq!(1, drop_counter.count());
+        drop(dyn_items);
+        assert_eq!(1, drop_counter.count());
+
+        drop(DynLen::from_ptr(Box::new([Some(drop_counter.clone())])));
+        assert_eq!(2, drop_counter.count());
+    }
+
+    #[test]
+    fn test_drop_rc() {
+        let drop_counter = DropCounter::new();
+        let rc_items = Rc::new([Some(drop_counter.clone())]);
+        let dyn_items = DynLen::from_ptr(Rc::clone(&rc_items));
+        assert_eq!(2, Rc::strong_count(&rc_items));
+        drop(dyn_items);
+        assert_eq!(1, Rc::strong_count(&rc_items));
+        drop(DynLen::from_ptr(rc_items));
+        assert_eq!(1, drop_counter.count());
+    }
+
+    #[test]
+    fn test_drop_ref() {
+        let drop_counter = DropCounter::new();
+        let local_items = [Some(drop_counter.clone())];
+        let dyn_items = DynLen::from_ptr(&local_items);
+        let dyn_items2 = DynLen::from_ptr(&local_items);
+        assert_eq!(get_len(&*dyn_items), get_len(&*dyn_items2));
+        drop(dyn_items);
+        drop(dyn_items2);
+        assert_eq!(0, drop_counter.count());
+        assert!(local_items[0].is_some());
+    }
+
+    #[test]
+    fn test_drop_mut() {
+        let drop_counter = DropCounter::new();
+        let mut local_items = [Some(drop_counter.clone()), None];
+        let mut dyn_items = DynLen::from_ptr(&mut local_items);
+        dyn_items.modify();
+        drop(dyn_items);
+        assert_eq!(1, drop_counter.count());
+        local_items[1] = Some(drop_counter.clone());
+        DynLen::from_ptr(&mut local_items).modify();
+        assert_eq!(1, drop_counter.count());
+        drop(local_items);
+        assert_eq!(2, drop_counter.count());
+    }
 }
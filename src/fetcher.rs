@@ -0,0 +1,100 @@
+//! Erasing `async fn` methods with `#[dyner]`.
+
+use dyner::dyner;
+use std::collections::HashMap;
+use std::future::Future;
+use tokio::task;
+
+#[dyner]
+pub trait Sink {
+    fn write(&mut self, bytes: &[u8]);
+}
+
+impl Sink for Vec<u8> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+#[dyner]
+pub trait Fetcher {
+    async fn fetch(&self, key: &str) -> Vec<u8>;
+    async fn fetch_into(&self, key: &str, sink: &mut impl Sink);
+    async fn store<'a>(&mut self, key: &'a str, value: &[u8]) -> Option<&'a str>;
+    fn len(&self) -> impl Future<Output = usize> + Send;
+    fn len_into(&self, sink: &mut impl Sink) -> impl Future<Output = ()>;
+}
+
+#[derive(Default)]
+struct MapFetcher {
+    map: HashMap<String, Vec<u8>>,
+}
+
+impl Fetcher for MapFetcher {
+    async fn fetch(&self, key: &str) -> Vec<u8> {
+        task::yield_now().await;
+        self.map.get(key).cloned().unwrap_or_default()
+    }
+
+    async fn fetch_into(&self, key: &str, sink: &mut impl Sink) {
+        let bytes = self.fetch(key).await;
+        sink.write(&bytes);
+    }
+
+    async fn store<'a>(&mut self, key: &'a str, value: &[u8]) -> Option<&'a str> {
+        task::yield_now().await;
+        self.map
+            .insert(key.to_string(), value.to_vec())
+            .map(|_| key)
+    }
+
+    fn len(&self) -> impl Future<Output = usize> + Send {
+        let len = self.map.len();
+        async move {
+            task::yield_now().await;
+            len
+        }
+    }
+
+    async fn len_into(&self, sink: &mut impl Sink) {
+        let len = self.len().await;
+        sink.write(&len.to_le_bytes()[..1]);
+    }
+}
+
+async fn store_and_fetch(fetcher: &mut DynFetcher<'_>) {
+    assert_eq!(fetcher.store("a", b"apple").await, None);
+    assert_eq!(fetcher.store("a", b"avocado").await, Some("a"));
+    assert_eq!(fetcher.fetch("a").await, b"avocado");
+    assert_eq!(fetcher.fetch("b").await, b"");
+    assert_eq!(fetcher.len().await, 1);
+
+    let mut sink = vec![];
+    fetcher.fetch_into("a", &mut sink).await;
+    fetcher.len_into(&mut sink).await;
+    assert_eq!(sink, b"avocado\x01");
+}
+
+#[tokio::test]
+async fn dyn_fetcher_new() {
+    let mut fetcher = DynFetcher::new(MapFetcher::default());
+    store_and_fetch(&mut fetcher).await;
+}
+
+#[tokio::test]
+async fn dyn_fetcher_from_mut() {
+    let mut map_fetcher = MapFetcher::default();
+    store_and_fetch(&mut DynFetcher::from_ptr(&mut map_fetcher)).await;
+    assert_eq!(map_fetcher.map["a"], b"avocado");
+}
+
+#[tokio::test]
+async fn dyn_fetcher_len_is_send() {
+    let map_fetcher = MapFetcher::default();
+    let fetcher = DynFetcher::from_ptr(&map_fetcher);
+    let len = fetcher.len();
+    fn assert_send<T: Send>(t: T) -> T {
+        t
+    }
+    assert_eq!(assert_send(len).await, 0);
+}
@@ -0,0 +1,41 @@
+//! Flavors select which auto traits an erased object (e.g.,
+//! `DynAsyncIter<'_, Item, F>`) and the futures it returns implement.
+//! Constructing an object of a given flavor requires the underlying value
+//! (and its futures) to implement the same auto traits.
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+
+pub trait Flavor {
+    /// The type of boxed future returned by erased async methods.
+    type BoxFuture<'a, T: 'a>: Future<Output = T> + 'a;
+}
+
+/// Neither the object nor its futures are `Send` or `Sync`. This is the
+/// default.
+pub struct Local;
+
+impl Flavor for Local {
+    type BoxFuture<'a, T: 'a> = Pin<Box<dyn Future<Output = T> + 'a>>;
+}
+
+/// The object and its futures are `Send`, so they can be moved to another
+/// thread (e.g., with `tokio::spawn`).
+///
+/// The underlying object must be `'static`, though the pointer to it may
+/// borrow it (see [`DynAsyncIter`]).
+///
+/// [`DynAsyncIter`]: crate::async_iter::DynAsyncIter
+pub struct Send;
+
+impl Flavor for Send {
+    type BoxFuture<'a, T: 'a> = Pin<Box<dyn Future<Output = T> + core::marker::Send + 'a>>;
+}
+
+/// Like [`Send`], but the object is also `Sync`. It must also be `'static`.
+pub struct Sync;
+
+impl Flavor for Sync {
+    type BoxFuture<'a, T: 'a> = Pin<Box<dyn Future<Output = T> + core::marker::Send + 'a>>;
+}
@@ -1,47 +1,147 @@
 use crate::async_iter::AsyncIter;
-use crate::dyner::InlineFuture;
-use std::cell::RefCell;
-use std::mem::MaybeUninit;
+use crate::dyner::{InlineFuture, InlineSlot};
+use core::fmt;
+use core::pin::Pin;
+
+/// How many `size_hint` futures an `InlineAsyncIterImpl` can hold at once.
+pub const SIZE_HINT_SLOTS: usize = 2;
+
+/// Returned by `try_size_hint` when all [`SIZE_HINT_SLOTS`] slots are held
+/// by outstanding futures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotsInUse;
+
+impl fmt::Display for SlotsInUse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "all {SIZE_HINT_SLOTS} size_hint slots are in use")
+    }
+}
+
+impl core::error::Error for SlotsInUse {}
 
 pub struct InlineDynAsyncIter<'me, Item> {
-    obj: &'me mut dyn InlineAsyncIter<Item = Item>,
+    obj: Pin<&'me mut dyn InlineAsyncIter<Item = Item>>,
 }
 
-trait InlineAsyncIter {
+pub(crate) trait InlineAsyncIter {
     type Item;
-    fn next(&mut self) -> crate::dyner::InlineFuture<'_, Option<Self::Item>>;
-    fn size_hint(&self) -> crate::dyner::InlineRefCellFuture<'_, Option<usize>>;
+    fn next(self: Pin<&mut Self>) -> InlineFuture<'_, Option<Self::Item>>;
+    fn size_hint(self: Pin<&Self>) -> InlineFuture<'_, Option<usize>>;
+    fn try_size_hint(self: Pin<&Self>) -> Result<InlineFuture<'_, Option<usize>>, SlotsInUse>;
 }
 
 impl<'me, Item> AsyncIter for InlineDynAsyncIter<'me, Item> {
     type Item = Item;
 
     type Next<'a>
+        = InlineFuture<'a, Option<Self::Item>>
     where
-        Self: 'a,
-    = crate::dyner::InlineFuture<'a, Option<Self::Item>>;
+        Self: 'a;
 
     fn next(&mut self) -> Self::Next<'_> {
-        InlineAsyncIter::next(self.obj)
+        self.obj.as_mut().next()
     }
 
     type SizeHint<'a>
+        = InlineFuture<'a, Option<usize>>
     where
-        Self: 'a,
-    = crate::dyner::InlineRefCellFuture<'a, Option<usize>>;
+        Self: 'a;
 
+    /// # Panics
+    ///
+    /// Without the `alloc` feature, if all [`SIZE_HINT_SLOTS`] slots are in
+    /// use, as the future can't be boxed instead. Use
+    /// [`InlineDynAsyncIter::try_size_hint`] to get an error instead.
     fn size_hint(&self) -> Self::SizeHint<'_> {
-        InlineAsyncIter::size_hint(self.obj)
+        self.obj.as_ref().size_hint()
     }
 }
 
-struct InlineAsyncIterImpl<'me, I>
+impl<'me, Item> InlineDynAsyncIter<'me, Item> {
+    /// Like `size_hint`, but returns an error instead of boxing the future if
+    /// all the slots are in use. Without the `alloc` feature, use this rather
+    /// than `size_hint`, which panics then.
+    pub fn try_size_hint(&self) -> Result<InlineFuture<'_, Option<usize>>, SlotsInUse> {
+        self.obj.as_ref().try_size_hint()
+    }
+}
+
+/// Storage for an iterator `I` along with its `next` future and up to
+/// [`SIZE_HINT_SLOTS`] `size_hint` futures, so that they can be returned
+/// through a `dyn` without boxing. Any further `size_hint` futures are
+/// boxed, or, without the `alloc` feature, panic (use `try_size_hint`).
+///
+/// The slots track whether they hold a future, so that it's dropped exactly
+/// once even if its `InlineFuture` is forgotten: in that case it's dropped
+/// by the next call to `next` (before it borrows the iterator mutably), when
+/// its slot is next filled, or when we are.
+///
+/// The futures are polled in place, so the storage must be pinned before
+/// use, e.g. with `std::pin::pin!`:
+///
+/// ```
+/// use dyner::async_iter::AsyncIter;
+/// use dyner::inline_async_iter::InlineAsyncIterImpl;
+/// use std::pin::pin;
+/// # use std::future::{ready, Ready};
+/// #
+/// # struct Count(u32, u32);
+/// #
+/// # impl AsyncIter for Count {
+/// #     type Item = u32;
+/// #     type Next<'me> = Ready<Option<u32>>;
+/// #     fn next(&mut self) -> Ready<Option<u32>> {
+/// #         let next = (self.0 < self.1).then_some(self.0);
+/// #         self.0 += next.is_some() as u32;
+/// #         ready(next)
+/// #     }
+/// #     type SizeHint<'me> = Ready<Option<usize>>;
+/// #     fn size_hint(&self) -> Ready<Option<usize>> {
+/// #         ready(Some((self.1 - self.0) as usize))
+/// #     }
+/// # }
+/// #
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let storage = pin!(InlineAsyncIterImpl::new(Count(0, 3)));
+/// let mut iter = storage.as_dyn();
+/// assert_eq!(iter.next().await, Some(0));
+/// # });
+/// ```
+///
+/// It can't be used unpinned:
+///
+/// ```compile_fail
+/// use dyner::inline_async_iter::InlineAsyncIterImpl;
+/// use std::pin::Pin;
+/// # use dyner::async_iter::AsyncIter;
+/// # use std::future::{ready, Ready};
+/// #
+/// # struct Count(u32, u32);
+/// #
+/// # impl AsyncIter for Count {
+/// #     type Item = u32;
+/// #     type Next<'me> = Ready<Option<u32>>;
+/// #     fn next(&mut self) -> Ready<Option<u32>> {
+/// #         let next = (self.0 < self.1).then_some(self.0);
+/// #         self.0 += next.is_some() as u32;
+/// #         ready(next)
+/// #     }
+/// #     type SizeHint<'me> = Ready<Option<usize>>;
+/// #     fn size_hint(&self) -> Ready<Option<usize>> {
+/// #         ready(Some((self.1 - self.0) as usize))
+/// #     }
+/// # }
+/// #
+/// let mut storage = InlineAsyncIterImpl::new(Count(0, 3));
+/// Pin::new(&mut storage).as_dyn();
+/// ```
+pub struct InlineAsyncIterImpl<'me, I>
 where
     I: AsyncIter + 'me,
 {
     underlying_impl: I,
-    next_future: MaybeUninit<I::Next<'me>>,
-    size_hint_future: RefCell<MaybeUninit<I::SizeHint<'me>>>,
+    next_future: InlineSlot<I::Next<'me>>,
+    size_hint_slots: [InlineSlot<I::SizeHint<'me>>; SIZE_HINT_SLOTS],
 }
 
 impl<'me, I> InlineAsyncIterImpl<'me, I>
@@ -51,12 +151,12 @@ where
     pub fn new(underlying: I) -> Self {
         Self {
             underlying_impl: underlying,
-            next_future: MaybeUninit::uninit(),
-            size_hint_future: RefCell::new(MaybeUninit::uninit()),
+            next_future: InlineSlot::new(),
+            size_hint_slots: core::array::from_fn(|_| InlineSlot::new()),
         }
     }
 
-    pub fn as_dyn(&mut self) -> InlineDynAsyncIter<'_, I::Item> {
+    pub fn as_dyn(self: Pin<&mut Self>) -> InlineDynAsyncIter<'_, I::Item> {
         InlineDynAsyncIter { obj: self }
     }
 }
@@ -67,40 +167,75 @@ where
 {
     type Item = I::Item;
 
-    fn next(&mut self) -> crate::dyner::InlineFuture<'_, Option<Self::Item>> {
-        let f: I::Next<'_> = self.underlying_impl.next();
+    fn next(self: Pin<&mut Self>) -> InlineFuture<'_, Option<Self::Item>> {
+        // The slots are structurally pinned; `underlying_impl` isn't.
+        let this = unsafe { self.get_unchecked_mut() };
 
-        // Extend the lifetime of `f` artificially to `'me`.
-        unsafe {
-            let f_ptr = std::ptr::addr_of!(f) as *mut I::Next<'me>;
-            self.next_future.write(std::ptr::read(f_ptr));
-            std::mem::forget(f);
+        // As we have `&mut self`, any futures left in the slots were
+        // forgotten. They still borrow `underlying_impl`, so drop them before
+        // we borrow it mutably.
+        unsafe { Pin::new_unchecked(&mut this.next_future) }.clear();
+        for slot in &mut this.size_hint_slots {
+            unsafe { Pin::new_unchecked(slot) }.clear();
         }
 
-        unsafe { InlineFuture::new(&mut self.next_future) }
-    }
+        let f: I::Next<'_> = this.underlying_impl.next();
+
+        // Extend the lifetime of `f` artificially to `'me`. It's dropped
+        // before `underlying_impl` is (see the `Drop` impl).
+        let f: I::Next<'me> = unsafe { core::mem::transmute(f) };
 
-    fn size_hint(&self) -> crate::dyner::InlineRefCellFuture<'_, Option<usize>> {
-        let f: I::SizeHint<'_> = self.underlying_impl.size_hint();
+        unsafe { Pin::new_unchecked(&mut this.next_future) }.fill(f)
+    }
 
-        // Extend the lifetime of `f` artificially to `'me`.
-        let maybe_uninit;
-        unsafe {
-            let f_ptr = std::ptr::addr_of!(f) as *mut I::SizeHint<'me>;
-            maybe_uninit = MaybeUninit::new(std::ptr::read(f_ptr));
-            std::mem::forget(f);
+    fn size_hint(self: Pin<&Self>) -> InlineFuture<'_, Option<usize>> {
+        match self.try_size_hint() {
+            Ok(future) => future,
+            #[cfg(feature = "alloc")]
+            Err(SlotsInUse) => InlineFuture::boxed(self.get_ref().underlying_impl.size_hint()),
+            #[cfg(not(feature = "alloc"))]
+            Err(SlotsInUse) => panic!("{}", SlotsInUse),
         }
+    }
+
+    fn try_size_hint(self: Pin<&Self>) -> Result<InlineFuture<'_, Option<usize>>, SlotsInUse> {
+        let this = self.get_ref();
+        let slot = this
+            .size_hint_slots
+            .iter()
+            .find(|slot| slot.is_free())
+            .ok_or(SlotsInUse)?;
+
+        let f: I::SizeHint<'_> = this.underlying_impl.size_hint();
+
+        // Extend the lifetime of `f` artificially to `'me`, as in `next`.
+        let f: I::SizeHint<'me> = unsafe { core::mem::transmute(f) };
+
+        Ok(unsafe { Pin::new_unchecked(slot) }
+            .try_fill(f)
+            .unwrap_or_else(|_| unreachable!("slot was free")))
+    }
+}
 
-        let mut r = self.size_hint_future.borrow_mut();
-        *r = maybe_uninit;
-        unsafe { crate::dyner::InlineRefCellFuture::new(r) }
+impl<'me, I> Drop for InlineAsyncIterImpl<'me, I>
+where
+    I: AsyncIter + 'me,
+{
+    fn drop(&mut self) {
+        // Drop any futures left by forgotten `InlineFuture`s while the
+        // `underlying_impl` they borrow is still alive.
+        unsafe { Pin::new_unchecked(&mut self.next_future) }.clear();
+        for slot in &mut self.size_hint_slots {
+            unsafe { Pin::new_unchecked(slot) }.clear();
+        }
     }
 }
 
+#[cfg(all(test, feature = "std"))]
 #[tokio::test]
 async fn inline_next() {
     let range = crate::yielding_range::YieldingRange::new(0, 10);
-    let mut inline_range = InlineAsyncIterImpl::new(range);
+    let inline_range = std::pin::pin!(InlineAsyncIterImpl::new(range));
     let mut inline_dyn_range: InlineDynAsyncIter<'_, u32> = inline_range.as_dyn();
     for i in 0..10 {
         assert_eq!(inline_dyn_range.next().await, Some(i));
@@ -111,37 +246,249 @@ async fn inline_next() {
 // #[tokio::test]
 // async fn next_error() {
 //     let range = crate::yielding_range::YieldingRange::new(0, 10);
-//     let mut inline_range = InlineAsyncIterImpl::new(range);
+//     let inline_range = std::pin::pin!(InlineAsyncIterImpl::new(range));
 //     let mut dyn_range = dyn_Range.as_dyn();
 //     let n1 = inline_range.next();
 //     let n2 = inline_range.next();
 // }
 
+#[cfg(all(test, feature = "std"))]
 #[tokio::test]
 async fn inline_size_hint() {
     let range = crate::yielding_range::YieldingRange::new(0, 10);
-    let mut inline_range = InlineAsyncIterImpl::new(range);
+    let inline_range = std::pin::pin!(InlineAsyncIterImpl::new(range));
     let inline_dyn_range: InlineDynAsyncIter<'_, u32> = inline_range.as_dyn();
     assert_eq!(inline_dyn_range.size_hint().await, Some(10));
 }
 
+#[cfg(all(test, feature = "std"))]
 #[tokio::test]
-#[should_panic(expected = "already borrowed: BorrowMutError")]
-async fn inline_size_hint_error() {
+async fn inline_try_size_hint_in_use() {
     let range = crate::yielding_range::YieldingRange::new(0, 10);
-    let mut inline_range = InlineAsyncIterImpl::new(range);
+    let inline_range = std::pin::pin!(InlineAsyncIterImpl::new(range));
     let inline_dyn_range: InlineDynAsyncIter<'_, u32> = inline_range.as_dyn();
-    let _s1 = inline_dyn_range.size_hint();
-    inline_dyn_range.size_hint(); // panics
+    let s1 = inline_dyn_range.try_size_hint().unwrap();
+    let s2 = inline_dyn_range.try_size_hint().unwrap();
+    assert_eq!(inline_dyn_range.try_size_hint().err(), Some(SlotsInUse));
+
+    // `size_hint` boxes instead.
+    let s3 = inline_dyn_range.size_hint();
+    assert_eq!(
+        (s1.await, s2.await, s3.await),
+        (Some(10), Some(10), Some(10))
+    );
+
+    // Awaiting (and so dropping) the futures freed their slots.
+    assert_eq!(inline_dyn_range.try_size_hint().unwrap().await, Some(10));
 }
 
+#[cfg(all(test, feature = "std"))]
 #[tokio::test]
 async fn inline_size_hint_ok() {
     let range = crate::yielding_range::YieldingRange::new(0, 10);
-    let mut inline_range = InlineAsyncIterImpl::new(range);
+    let inline_range = std::pin::pin!(InlineAsyncIterImpl::new(range));
     let inline_dyn_range: InlineDynAsyncIter<'_, u32> = inline_range.as_dyn();
     let s1 = inline_dyn_range.size_hint().await;
     let s2 = inline_dyn_range.size_hint().await;
     assert_eq!(s1, s2);
     assert_eq!(s1, Some(10));
 }
+
+// These exercise the slots' lifecycle tracking, so they should also pass
+// under `cargo miri test inline_async_iter`.
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::future::Future;
+    use std::marker::PhantomData;
+    use std::pin::{pin, Pin};
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+
+    /// An `AsyncIter` whose futures borrow it, and count their drops.
+    #[derive(Default)]
+    struct Drops(Rc<Cell<usize>>);
+
+    struct Counted<'a, T> {
+        drops: &'a Cell<usize>,
+        _output: PhantomData<T>,
+    }
+
+    impl<'a, T> Counted<'a, T> {
+        fn new(iter: &'a Drops) -> Self {
+            Counted {
+                drops: &iter.0,
+                _output: PhantomData,
+            }
+        }
+    }
+
+    impl<T: Default> Future for Counted<'_, T> {
+        type Output = T;
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<T> {
+            Poll::Ready(T::default())
+        }
+    }
+
+    impl<T> Drop for Counted<'_, T> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    impl AsyncIter for Drops {
+        type Item = ();
+
+        type Next<'a> = Counted<'a, Option<()>>;
+
+        fn next(&mut self) -> Self::Next<'_> {
+            Counted::new(self)
+        }
+
+        type SizeHint<'a> = Counted<'a, Option<usize>>;
+
+        fn size_hint(&self) -> Self::SizeHint<'_> {
+            Counted::new(self)
+        }
+    }
+
+    #[tokio::test]
+    async fn drop_unpolled() {
+        let drops = Drops::default();
+        let count = drops.0.clone();
+        let mut inline = pin!(InlineAsyncIterImpl::new(drops));
+        drop(inline.as_mut().as_dyn().next());
+        drop(inline.as_mut().as_dyn().size_hint());
+        assert_eq!(count.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn forget_next() {
+        let drops = Drops::default();
+        let count = drops.0.clone();
+        let mut inline = pin!(InlineAsyncIterImpl::new(drops));
+        std::mem::forget(inline.as_mut().as_dyn().next());
+        assert_eq!(count.get(), 0);
+
+        // The forgotten future is dropped before its slot is refilled.
+        assert_eq!(inline.as_mut().as_dyn().next().await, None);
+        assert_eq!(count.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn forget_size_hint() {
+        let drops = Drops::default();
+        let count = drops.0.clone();
+        let inline = pin!(InlineAsyncIterImpl::new(drops));
+        let dyn_inline = inline.as_dyn();
+        for _ in 0..SIZE_HINT_SLOTS {
+            std::mem::forget(dyn_inline.try_size_hint().unwrap());
+        }
+
+        // The slots stay in use, so further futures are boxed.
+        assert_eq!(dyn_inline.try_size_hint().err(), Some(SlotsInUse));
+        assert_eq!(dyn_inline.size_hint().await, None);
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn drop_with_forgotten_futures() {
+        let drops = Drops::default();
+        let count = drops.0.clone();
+        let mut inline = Box::pin(InlineAsyncIterImpl::new(drops));
+        let mut dyn_inline = inline.as_mut().as_dyn();
+        std::mem::forget(dyn_inline.next());
+        for _ in 0..SIZE_HINT_SLOTS {
+            std::mem::forget(dyn_inline.size_hint());
+        }
+        assert_eq!(count.get(), 0);
+
+        // The futures are dropped before the iterator they borrow.
+        drop(inline);
+        assert_eq!(count.get(), 1 + SIZE_HINT_SLOTS);
+    }
+
+    /// An `AsyncIter` whose futures write to it (`next`) or read from it
+    /// (`size_hint`) when dropped, so that Miri sees them used after a later
+    /// borrow of the iterator if they outlive it.
+    #[derive(Default)]
+    struct Touches {
+        count: u32,
+    }
+
+    struct WriteOnDrop<'a>(&'a mut u32);
+
+    impl Future for WriteOnDrop<'_> {
+        type Output = Option<u32>;
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<u32>> {
+            Poll::Ready(Some(*self.0))
+        }
+    }
+
+    impl Drop for WriteOnDrop<'_> {
+        fn drop(&mut self) {
+            *self.0 += 1;
+        }
+    }
+
+    struct ReadOnDrop<'a>(&'a u32);
+
+    impl Future for ReadOnDrop<'_> {
+        type Output = Option<usize>;
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<usize>> {
+            Poll::Ready(Some(*self.0 as usize))
+        }
+    }
+
+    impl Drop for ReadOnDrop<'_> {
+        fn drop(&mut self) {
+            std::hint::black_box(*self.0);
+        }
+    }
+
+    impl AsyncIter for Touches {
+        type Item = u32;
+
+        type Next<'a> = WriteOnDrop<'a>;
+
+        fn next(&mut self) -> Self::Next<'_> {
+            WriteOnDrop(&mut self.count)
+        }
+
+        type SizeHint<'a> = ReadOnDrop<'a>;
+
+        fn size_hint(&self) -> Self::SizeHint<'_> {
+            ReadOnDrop(&self.count)
+        }
+    }
+
+    #[tokio::test]
+    async fn forget_next_then_next() {
+        let mut inline = pin!(InlineAsyncIterImpl::new(Touches::default()));
+        let mut dyn_inline = inline.as_mut().as_dyn();
+        std::mem::forget(dyn_inline.next());
+
+        // The forgotten future is dropped (and writes) before `next` borrows
+        // the iterator again.
+        assert_eq!(dyn_inline.next().await, Some(1));
+        assert_eq!(dyn_inline.next().await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn forget_size_hint_then_next() {
+        let mut inline = pin!(InlineAsyncIterImpl::new(Touches::default()));
+        let mut dyn_inline = inline.as_mut().as_dyn();
+        for _ in 0..SIZE_HINT_SLOTS {
+            std::mem::forget(dyn_inline.try_size_hint().unwrap());
+        }
+
+        // The forgotten futures are dropped (and read) before `next` borrows
+        // the iterator mutably, which frees their slots too.
+        assert_eq!(dyn_inline.next().await, Some(0));
+        assert_eq!(dyn_inline.try_size_hint().unwrap().await, Some(1));
+    }
+}
@@ -0,0 +1,49 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "alloc", feature(allocator_api))]
+#![feature(arbitrary_self_types_pointers)]
+#![cfg_attr(all(test, feature = "std"), feature(impl_trait_in_assoc_type))]
+#![feature(ptr_metadata)]
+#![feature(unsize)]
+// We document unsafe functions with "Safe:"/"Unsafe:" lists instead.
+#![allow(clippy::missing_safety_doc)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub use dyner_macros::dyner;
+
+/// Used by `#[dyner]` generated code.
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(feature = "alloc")]
+    pub use alloc::boxed::Box;
+}
+
+/// Expands to its input if we have the `alloc` feature, and to nothing
+/// otherwise. Used by `#[dyner]` to only generate `new` with `alloc`.
+#[cfg(feature = "alloc")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_alloc {
+    ($($item:tt)*) => { $($item)* };
+}
+
+#[cfg(not(feature = "alloc"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_alloc {
+    ($($item:tt)*) => {};
+}
+
+pub mod async_iter;
+pub mod dyn_async_iter;
+pub mod dyn_ptr;
+pub mod dyner;
+#[allow(dead_code)]
+mod dynerx;
+#[cfg(feature = "alloc")]
+pub mod flavor;
+pub mod inline_async_iter;
+// Also a module of the binary, whose tests use it too.
+#[cfg(all(test, feature = "std"))]
+mod yielding_range;
@@ -1,16 +1,51 @@
-#![feature(generic_associated_types)]
-#![feature(type_alias_impl_trait)]
-
-mod async_iter;
-mod dyn_async_iter;
-mod dyner;
-mod dynerx;
-mod inline_async_iter;
-mod yielding_range;
+#![cfg_attr(test, feature(allocator_api, impl_trait_in_assoc_type))]
+
+#[cfg(test)]
+use dyner::{
+    async_iter::{self, AsyncIter},
+    dyn_async_iter,
+    flavor::{self, Flavor},
+    inline_async_iter,
+};
+
+#[cfg(test)]
+mod bump;
+
+#[cfg(test)]
+mod counting_alloc;
+
+#[cfg(test)]
+mod fetcher;
+
+#[cfg(test)]
+mod quick_start;
+
+#[cfg(test)]
+mod recorder;
 
-use async_iter::AsyncIter;
+#[cfg(test)]
+mod service;
+
+#[cfg(test)]
+mod shapes;
+
+#[cfg(test)]
+mod std_traits;
+
+#[cfg(test)]
+mod views;
+
+#[cfg(test)]
+mod visitor;
+
+#[cfg(test)]
+mod yielding_range;
 
-async fn do_loop(range: std::ops::Range<u32>, data: &mut async_iter::DynAsyncIter<'_, u32>) {
+#[cfg(test)]
+async fn do_loop<F: Flavor>(
+    range: std::ops::Range<u32>,
+    data: &mut async_iter::DynAsyncIter<'_, u32, F>,
+) {
     for i in range {
         match data.next().await {
             Some(j) => assert_eq!(i, j),
@@ -26,10 +61,41 @@ async fn box_dyn_async_iter() {
     do_loop(0..10, &mut dyn_range).await;
 }
 
+/// An allocator that counts its live allocations.
+#[cfg(test)]
+struct Live(std::cell::Cell<usize>);
+
+#[cfg(test)]
+unsafe impl std::alloc::Allocator for &Live {
+    fn allocate(
+        &self,
+        layout: std::alloc::Layout,
+    ) -> Result<std::ptr::NonNull<[u8]>, std::alloc::AllocError> {
+        self.0.set(self.0.get() + 1);
+        std::alloc::Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: std::ptr::NonNull<u8>, layout: std::alloc::Layout) {
+        self.0.set(self.0.get() - 1);
+        std::alloc::Global.deallocate(ptr, layout)
+    }
+}
+
+#[tokio::test]
+async fn box_in_dyn_async_iter() {
+    let live = Live(std::cell::Cell::new(0));
+    let range = yielding_range::YieldingRange::new(0, 10);
+    let mut dyn_range = async_iter::DynAsyncIter::new_in(range, &live);
+    assert_eq!(live.0.get(), 1);
+    do_loop(0..10, &mut dyn_range).await;
+    drop(dyn_range);
+    assert_eq!(live.0.get(), 0);
+}
+
 #[tokio::test]
 async fn ref_mut_dyn_async_iter() {
     let mut range = yielding_range::YieldingRange::new(0, 10);
-    let mut dyn_range = async_iter::DynAsyncIter::from_ref_mut(&mut range);
+    let mut dyn_range = async_iter::DynAsyncIter::from_ptr(&mut range);
     do_loop(0..10, &mut dyn_range).await;
 }
 
@@ -49,7 +115,7 @@ async fn ref_mut_dyn_async_iter() {
 ///
 /// ```compile_fail
 /// let range = yielding_range::YieldingRange::new(0, 10);
-/// let mut dyn_range = async_iter::DynAsyncIter::from_ref(&range);
+/// let mut dyn_range = async_iter::DynAsyncIter::from_ptr(&range);
 /// dyn_range.next().await;
 /// ```
 ///
@@ -60,8 +126,250 @@ async fn ref_dyn_async_iter() {}
 #[tokio::test]
 async fn ref_dyn_async_iter_size_hint() {
     let range = yielding_range::YieldingRange::new(0, 10);
-    let dyn_range = async_iter::DynAsyncIter::from_ref(&range);
+    let dyn_range = async_iter::DynAsyncIter::from_ptr(&range);
     assert_eq!(dyn_range.size_hint().await, Some(10));
 }
 
-fn main() {}
+#[tokio::test]
+async fn send_dyn_async_iter() {
+    let range = yielding_range::YieldingRange::new(0, 10);
+    let mut dyn_range: async_iter::DynAsyncIter<'static, u32, flavor::Send> =
+        async_iter::DynAsyncIter::new_flavored(range);
+    tokio::spawn(async move { do_loop(0..10, &mut dyn_range).await })
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn send_ref_mut_dyn_async_iter() {
+    tokio::spawn(async move {
+        let mut range = yielding_range::YieldingRange::new(0, 10);
+        let mut dyn_range =
+            async_iter::DynAsyncIter::<u32, flavor::Send>::from_ptr_flavored(&mut range);
+        do_loop(0..10, &mut dyn_range).await;
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn sync_dyn_async_iter() {
+    let range = yielding_range::YieldingRange::new(0, 10);
+    let dyn_range: std::sync::Arc<async_iter::DynAsyncIter<'static, u32, flavor::Sync>> =
+        std::sync::Arc::new(async_iter::DynAsyncIter::new_flavored(range));
+    let tasks: Vec<_> = (0..4)
+        .map(|_| {
+            let dyn_range = dyn_range.clone();
+            tokio::spawn(async move { dyn_range.size_hint().await })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), Some(10));
+    }
+}
+
+/// A one-byte iterator, so it can live at an odd address.
+#[cfg(test)]
+struct Countdown(u8);
+
+#[cfg(test)]
+impl AsyncIter for Countdown {
+    type Item = u8;
+
+    type Next<'me> = std::future::Ready<Option<u8>>;
+
+    fn next(&mut self) -> Self::Next<'_> {
+        let next = self.0.checked_sub(1);
+        if let Some(n) = next {
+            self.0 = n;
+        }
+        std::future::ready(next)
+    }
+
+    type SizeHint<'me> = std::future::Ready<Option<usize>>;
+
+    fn size_hint(&self) -> Self::SizeHint<'_> {
+        std::future::ready(Some(self.0 as usize))
+    }
+}
+
+#[tokio::test]
+async fn u8_aligned_dyn_async_iter() {
+    let mut countdowns = [Countdown(2), Countdown(3)];
+    assert_eq!(std::mem::align_of::<Countdown>(), 1);
+    // One of these is at an odd address.
+    for (countdown, start) in countdowns.iter_mut().zip([2, 3]) {
+        let mut dyn_countdown = async_iter::DynAsyncIter::from_ptr(countdown);
+        assert_eq!(dyn_countdown.size_hint().await, Some(start as usize));
+        for i in (0..start).rev() {
+            assert_eq!(dyn_countdown.next().await, Some(i));
+        }
+        assert_eq!(dyn_countdown.next().await, None);
+    }
+    assert!(countdowns.iter().all(|c| c.0 == 0));
+
+    let mut dyn_countdown = async_iter::DynAsyncIter::new(Countdown(1));
+    assert_eq!(dyn_countdown.next().await, Some(0));
+    assert_eq!(dyn_countdown.next().await, None);
+}
+
+/// A zero-sized iterator, whose box is a dangling (here, odd) pointer.
+#[cfg(test)]
+struct Empty;
+
+#[cfg(test)]
+impl AsyncIter for Empty {
+    type Item = ();
+
+    type Next<'me> = std::future::Ready<Option<()>>;
+
+    fn next(&mut self) -> Self::Next<'_> {
+        std::future::ready(None)
+    }
+
+    type SizeHint<'me> = std::future::Ready<Option<usize>>;
+
+    fn size_hint(&self) -> Self::SizeHint<'_> {
+        std::future::ready(Some(0))
+    }
+}
+
+#[tokio::test]
+async fn zero_sized_dyn_async_iter() {
+    let mut dyn_empty = async_iter::DynAsyncIter::new(Empty);
+    assert_eq!(dyn_empty.size_hint().await, Some(0));
+    assert_eq!(dyn_empty.next().await, None);
+}
+
+/// As `u8_aligned_dyn_async_iter`, through a `Boxing` `dyn_async_iter::DynAsyncIter`.
+#[tokio::test]
+async fn u8_aligned_boxing_dyn_async_iter() {
+    type DynBoxing<'me> = dyn_async_iter::DynAsyncIter<'me, dyn_async_iter::Boxing<u8>>;
+
+    let mut countdowns = [Countdown(2), Countdown(3)];
+    // One of these is at an odd address.
+    for (countdown, start) in countdowns.iter_mut().zip([2, 3]) {
+        let mut dyn_countdown = DynBoxing::from_mut(countdown);
+        assert_eq!(dyn_countdown.size_hint().await, Some(start as usize));
+        for i in (0..start).rev() {
+            assert_eq!(dyn_countdown.next().await, Some(i));
+        }
+        assert_eq!(dyn_countdown.next().await, None);
+    }
+    assert!(countdowns.iter().all(|c| c.0 == 0));
+
+    let mut dyn_countdown = DynBoxing::new(Countdown(1));
+    assert_eq!(dyn_countdown.next().await, Some(0));
+    assert_eq!(dyn_countdown.next().await, None);
+}
+
+/// As `zero_sized_dyn_async_iter`, through a `Boxing` `dyn_async_iter::DynAsyncIter`.
+#[tokio::test]
+async fn zero_sized_boxing_dyn_async_iter() {
+    type DynBoxing<'me> = dyn_async_iter::DynAsyncIter<'me, dyn_async_iter::Boxing<()>>;
+
+    let mut dyn_empty = DynBoxing::new(Empty);
+    assert_eq!(dyn_empty.size_hint().await, Some(0));
+    assert_eq!(dyn_empty.next().await, None);
+
+    let mut empty = Empty;
+    let mut dyn_empty = DynBoxing::from_mut(&mut empty);
+    assert_eq!(dyn_empty.size_hint().await, Some(0));
+    assert_eq!(dyn_empty.next().await, None);
+}
+
+/// Like `do_loop`, but for any `AsyncIter`.
+#[cfg(test)]
+async fn do_any_loop(range: std::ops::Range<u32>, data: &mut impl AsyncIter<Item = u32>) {
+    for i in range {
+        match data.next().await {
+            Some(j) => assert_eq!(i, j),
+            None => panic!("expected {} found None", i),
+        }
+    }
+}
+
+/// Counts the allocations made by `do_loop` over a boxing `DynAsyncIter`,
+/// which boxes each `next()` future, over a `Cached` one, which reuses the
+/// slot allocated along with the iterator, over a `Small` one, which stores
+/// each future inside the `SmallFuture` it returns, and over an `Inline`
+/// one, which borrows its slot from the stack.
+#[tokio::test]
+async fn dyn_async_iter_allocations() {
+    const N: u32 = 100;
+
+    let boxing = counting_alloc::count(async {
+        let range = yielding_range::YieldingRange::new(0, N);
+        do_loop(0..N, &mut async_iter::DynAsyncIter::new(range)).await
+    })
+    .await;
+
+    let cached = counting_alloc::count(async {
+        let range = yielding_range::YieldingRange::new(0, N);
+        let mut dyn_range = dyn_async_iter::DynAsyncIter::<dyn_async_iter::Cached<_>>::new(range);
+        do_any_loop(0..N, &mut dyn_range).await
+    })
+    .await;
+
+    let small = counting_alloc::count(async {
+        let range = yielding_range::YieldingRange::new(0, N);
+        let mut dyn_range =
+            dyn_async_iter::DynAsyncIter::<dyn_async_iter::Small<_, 64>>::new(range);
+        do_any_loop(0..N, &mut dyn_range).await
+    })
+    .await;
+
+    let inline = counting_alloc::count(async {
+        let range = yielding_range::YieldingRange::new(0, N);
+        let storage = std::pin::pin!(inline_async_iter::InlineAsyncIterImpl::new(range));
+        let mut dyn_range = dyn_async_iter::DynAsyncIter::inline(storage);
+        do_any_loop(0..N, &mut *dyn_range).await
+    })
+    .await;
+
+    assert!(boxing > N as usize, "{boxing} allocations");
+    assert_eq!(cached, 1);
+    assert_eq!(small, 1);
+    assert_eq!(inline, 0);
+}
+
+/// Boxing futures in a bump arena instead of the global heap means that
+/// `do_any_loop` makes no global allocations at all.
+#[tokio::test]
+async fn arena_dyn_async_iter_allocations() {
+    const N: u32 = 100;
+
+    let arena = bump::Bump::<{ 64 * 1024 }>::new();
+    let range = yielding_range::YieldingRange::new(0, N);
+    // Safe: we don't leak any futures.
+    let mut dyn_range = unsafe {
+        dyn_async_iter::DynAsyncIter::<dyn_async_iter::Arena<_, _>>::new_in_arena(range, &arena)
+    };
+
+    // tokio allocates the first time a task yields, so get that out of the
+    // way first.
+    tokio::task::yield_now().await;
+
+    let allocations = counting_alloc::count(do_any_loop(0..N, &mut dyn_range)).await;
+    assert_eq!(allocations, 0);
+    assert!(arena.used() > 0);
+}
+
+/// With `new_in`, the object goes in the arena too.
+#[tokio::test]
+async fn arena_small_dyn_async_iter_allocations() {
+    const N: u32 = 100;
+
+    let arena = bump::Bump::<1024>::new();
+    tokio::task::yield_now().await;
+
+    let allocations = counting_alloc::count(async {
+        let range = yielding_range::YieldingRange::new(0, N);
+        let mut dyn_range =
+            dyn_async_iter::DynAsyncIter::<dyn_async_iter::Small<_, 64>>::new_in(range, &arena);
+        do_any_loop(0..N, &mut dyn_range).await
+    })
+    .await;
+    assert_eq!(allocations, 0);
+    assert!(arena.used() > 0);
+}
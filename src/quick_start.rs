@@ -0,0 +1,197 @@
+//! The examples from the "Quick start" section of the book, checked as tests.
+
+use dyner::dyner;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[dyner]
+pub trait Screen {
+    fn put(&mut self, ch: char, x: u32, y: u32);
+    fn width(&self) -> u32;
+}
+
+#[derive(Default)]
+struct VecScreen {
+    puts: Vec<(char, u32, u32)>,
+}
+
+impl Screen for VecScreen {
+    fn put(&mut self, ch: char, x: u32, y: u32) {
+        self.puts.push((ch, x, y));
+    }
+
+    fn width(&self) -> u32 {
+        80
+    }
+}
+
+#[dyner]
+pub trait Draw {
+    fn draw(&self, screen: &mut impl Screen);
+    fn fits(&self, screen: &impl Screen) -> bool;
+    fn draw_into(&self, screen: impl Screen);
+}
+
+struct Rectangle {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+impl Rectangle {
+    fn new(x: u32, y: u32, w: u32, h: u32) -> Self {
+        Self { x, y, w, h }
+    }
+}
+
+impl Draw for Rectangle {
+    fn draw(&self, screen: &mut impl Screen) {
+        screen.put('+', self.x, self.y);
+        screen.put('+', self.x + self.w, self.y + self.h);
+    }
+
+    fn fits(&self, screen: &impl Screen) -> bool {
+        self.x + self.w < screen.width()
+    }
+
+    fn draw_into(&self, mut screen: impl Screen) {
+        self.draw(&mut screen)
+    }
+}
+
+struct Circle {
+    x: u32,
+    y: u32,
+    r: u32,
+}
+
+impl Circle {
+    fn new(x: u32, y: u32, r: u32) -> Self {
+        Self { x, y, r }
+    }
+}
+
+impl Draw for Circle {
+    fn draw(&self, screen: &mut impl Screen) {
+        screen.put('o', self.x, self.y);
+    }
+
+    fn fits(&self, screen: &impl Screen) -> bool {
+        self.x + self.r < screen.width()
+    }
+
+    fn draw_into(&self, mut screen: impl Screen) {
+        self.draw(&mut screen)
+    }
+}
+
+/// A screen that can be passed by value but still inspected afterwards.
+#[derive(Clone, Default)]
+struct SharedScreen {
+    puts: Rc<RefCell<Vec<(char, u32, u32)>>>,
+}
+
+impl Screen for SharedScreen {
+    fn put(&mut self, ch: char, x: u32, y: u32) {
+        self.puts.borrow_mut().push((ch, x, y));
+    }
+
+    fn width(&self) -> u32 {
+        40
+    }
+}
+
+fn make_drawables() -> Vec<DynDraw<'static>> {
+    let r = Rectangle::new(0, 0, 10, 10);
+    let c = Circle::new(22, 44, 66);
+    vec![DynDraw::new(r), DynDraw::new(c)]
+}
+
+fn draw_all(draws: &[DynDraw<'_>], screen: &mut impl Screen) {
+    for draw in draws {
+        draw.draw(screen);
+    }
+}
+
+fn draw_all_dyn(draws: &[DynDraw<'_>], screen: &mut DynScreen<'_>) {
+    for draw in draws {
+        draw.draw(screen);
+    }
+}
+
+fn draw_four(draw: &impl Draw, screen: &mut impl Screen) {
+    let draw_ref = DynDraw::from_ptr(draw);
+    let mut screen_mut = DynScreen::from_ptr(screen);
+    draw_two(&draw_ref, &mut screen_mut);
+    draw_two(&draw_ref, &mut screen_mut);
+}
+
+fn draw_two(draw: &DynDraw<'_>, screen: &mut DynScreen<'_>) {
+    draw.draw(screen);
+    draw.draw(screen);
+}
+
+fn put_twice(screen: &mut DynScreen<'_>) {
+    screen.put('a', 0, 0);
+    screen.put('b', 1, 1);
+}
+
+#[test]
+fn dyn_screen_new() {
+    let mut screen = DynScreen::new(VecScreen::default());
+    put_twice(&mut screen);
+    assert_eq!(screen.width(), 80);
+}
+
+#[test]
+fn dyn_screen_from_mut() {
+    let mut vec_screen = VecScreen::default();
+    put_twice(&mut DynScreen::from_ptr(&mut vec_screen));
+    assert_eq!(vec_screen.puts, vec![('a', 0, 0), ('b', 1, 1)]);
+}
+
+#[test]
+fn dyn_screen_from_ref() {
+    let vec_screen = VecScreen::default();
+    let screen = DynScreen::from_ptr(&vec_screen);
+    assert_eq!(screen.width(), 80);
+}
+
+#[test]
+fn draw_all_impl_screen() {
+    let mut screen = VecScreen::default();
+    draw_all(&make_drawables(), &mut screen);
+    assert_eq!(screen.puts, vec![('+', 0, 0), ('+', 10, 10), ('o', 22, 44)]);
+}
+
+#[test]
+fn draw_all_dyn_screen() {
+    let mut screen = VecScreen::default();
+    draw_all_dyn(&make_drawables(), &mut DynScreen::from_ptr(&mut screen));
+    assert_eq!(screen.puts.len(), 3);
+}
+
+#[test]
+fn draw_four_from_ref() {
+    let mut screen = VecScreen::default();
+    draw_four(&Circle::new(1, 2, 3), &mut screen);
+    assert_eq!(screen.puts, vec![('o', 1, 2); 4]);
+}
+
+#[test]
+fn fits_ref_impl_screen() {
+    let drawables = make_drawables();
+    let screen = VecScreen::default();
+    let fits: Vec<bool> = drawables.iter().map(|d| d.fits(&screen)).collect();
+    assert_eq!(fits, vec![true, false]);
+}
+
+#[test]
+fn draw_into_owned_impl_screen() {
+    let screen = SharedScreen::default();
+    for drawable in make_drawables() {
+        drawable.draw_into(screen.clone());
+    }
+    assert_eq!(screen.puts.borrow().len(), 3);
+}
@@ -0,0 +1,115 @@
+//! Erasing by-value `self` methods with `#[dyner]`.
+
+use dyner::dyner;
+use std::cell::Cell;
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    events: Vec<String>,
+}
+
+#[dyner]
+pub trait Recorder {
+    fn record(&mut self, event: &str);
+    fn finish(self) -> Report;
+    async fn finish_async(self, event: &str) -> Report;
+}
+
+struct VecRecorder {
+    events: Vec<String>,
+    drops: Rc<Cell<usize>>,
+}
+
+impl VecRecorder {
+    fn new(drops: &Rc<Cell<usize>>) -> Self {
+        Self {
+            events: vec![],
+            drops: drops.clone(),
+        }
+    }
+}
+
+impl Drop for VecRecorder {
+    fn drop(&mut self) {
+        self.drops.set(self.drops.get() + 1);
+    }
+}
+
+impl Recorder for VecRecorder {
+    fn record(&mut self, event: &str) {
+        self.events.push(event.to_string());
+    }
+
+    fn finish(mut self) -> Report {
+        Report {
+            events: std::mem::take(&mut self.events),
+        }
+    }
+
+    async fn finish_async(mut self, event: &str) -> Report {
+        tokio::task::yield_now().await;
+        self.record(event);
+        self.finish()
+    }
+}
+
+#[test]
+fn finish_owned() {
+    let drops = Rc::new(Cell::new(0));
+    let mut recorder = DynRecorder::new(VecRecorder::new(&drops));
+    recorder.record("a");
+    recorder.record("b");
+    assert_eq!(drops.get(), 0);
+    let report = recorder.finish();
+    assert_eq!(drops.get(), 1);
+    assert_eq!(report.events, vec!["a", "b"]);
+}
+
+#[tokio::test]
+async fn finish_async_owned() {
+    let drops = Rc::new(Cell::new(0));
+    let mut recorder = DynRecorder::new(VecRecorder::new(&drops));
+    recorder.record("a");
+    let report = recorder.finish_async("b").await;
+    assert_eq!(drops.get(), 1);
+    assert_eq!(report.events, vec!["a", "b"]);
+}
+
+#[test]
+fn drop_owned_without_finish() {
+    let drops = Rc::new(Cell::new(0));
+    let mut recorder = DynRecorder::new(VecRecorder::new(&drops));
+    recorder.record("a");
+    drop(recorder);
+    assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn finish_from_box() {
+    let drops = Rc::new(Cell::new(0));
+    let recorder = DynRecorder::from_ptr(Box::new(VecRecorder::new(&drops)));
+    let report = recorder.finish();
+    assert_eq!(drops.get(), 1);
+    assert_eq!(report.events, Vec::<String>::new());
+}
+
+#[test]
+fn drop_from_rc() {
+    let drops = Rc::new(Cell::new(0));
+    let shared = Rc::new(VecRecorder::new(&drops));
+    drop(DynRecorder::from_ptr(shared.clone()));
+    assert_eq!(drops.get(), 0);
+    drop(DynRecorder::from_ptr(shared));
+    assert_eq!(drops.get(), 1);
+}
+
+#[test]
+#[should_panic(expected = "cannot call a `self` method on a borrowed object")]
+fn finish_swapped_out_of_ref_mut() {
+    let drops = Rc::new(Cell::new(0));
+    let mut borrowed = VecRecorder::new(&drops);
+    let mut owned = DynRecorder::new(VecRecorder::new(&drops));
+    std::mem::swap(&mut *DynRecorder::from_ptr(&mut borrowed), &mut owned);
+    owned.finish();
+}
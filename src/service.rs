@@ -0,0 +1,93 @@
+//! Erasing traits with associated types with `#[dyner]`.
+
+use dyner::dyner;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use tokio::task;
+
+#[dyner]
+pub trait Service {
+    type Request;
+    type Response: Debug + PartialEq;
+
+    fn call(&mut self, request: Self::Request) -> Self::Response;
+    async fn call_async(&self, request: <Self as Service>::Request) -> Option<Self::Response>;
+}
+
+struct Doubler;
+
+impl Service for Doubler {
+    type Request = u32;
+    type Response = u64;
+
+    fn call(&mut self, request: u32) -> u64 {
+        u64::from(request) * 2
+    }
+
+    async fn call_async(&self, request: u32) -> Option<u64> {
+        task::yield_now().await;
+        Some(u64::from(request) * 2)
+    }
+}
+
+/// Remembers the requests it has seen.
+#[derive(Default)]
+struct Counter {
+    seen: HashMap<u32, u64>,
+}
+
+impl Service for Counter {
+    type Request = u32;
+    type Response = u64;
+
+    fn call(&mut self, request: u32) -> u64 {
+        let count = self.seen.entry(request).or_default();
+        *count += 1;
+        *count
+    }
+
+    async fn call_async(&self, request: u32) -> Option<u64> {
+        task::yield_now().await;
+        self.seen.get(&request).copied()
+    }
+}
+
+fn call_all(services: &mut [DynService<'_, u32, u64>], request: u32) -> Vec<u64> {
+    services.iter_mut().map(|s| s.call(request)).collect()
+}
+
+/// `DynService` implements `Service`, so it can be passed on generically.
+fn call_twice<S: Service>(service: &mut S, request: S::Request) -> S::Response
+where
+    S::Request: Clone,
+{
+    service.call(request.clone());
+    service.call(request)
+}
+
+#[test]
+fn dyn_service_new() {
+    let mut services = vec![
+        DynService::new(Doubler),
+        DynService::new(Counter::default()),
+    ];
+    assert_eq!(call_all(&mut services, 3), vec![6, 1]);
+    assert_eq!(call_all(&mut services, 3), vec![6, 2]);
+}
+
+#[test]
+fn dyn_service_from_mut() {
+    let mut counter = Counter::default();
+    assert_eq!(call_twice(&mut *DynService::from_ptr(&mut counter), 22), 2);
+    assert_eq!(counter.seen[&22], 2);
+}
+
+#[tokio::test]
+async fn dyn_service_call_async() {
+    let mut counter = Counter::default();
+    counter.call(22);
+    let service = DynService::from_ptr(&counter);
+    assert_eq!(service.call_async(22).await, Some(1));
+    assert_eq!(service.call_async(23).await, None);
+    assert_eq!(DynService::new(Doubler).call_async(4).await, Some(8));
+}
@@ -0,0 +1,152 @@
+//! Supertraits of `#[dyner]` traits.
+
+use crate::bump::Bump;
+use dyner::dyner;
+use std::fmt::{self, Debug, Display};
+use tokio::task;
+
+mod named {
+    use dyner::dyner;
+
+    #[dyner]
+    pub trait Named {
+        fn name(&self) -> String;
+        fn rename(&mut self, name: &str);
+        fn into_name(self) -> String;
+        async fn describe(&self) -> String;
+    }
+}
+
+use named::{DynNamed, Named};
+
+#[dyner]
+pub trait Shape: named::Named + Debug + Display {
+    fn area(&self) -> f64;
+    fn grow(&mut self, factor: f64);
+}
+
+#[derive(Debug)]
+struct Square {
+    name: String,
+    side: f64,
+}
+
+impl Named for Square {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn rename(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    fn into_name(self) -> String {
+        self.name
+    }
+
+    async fn describe(&self) -> String {
+        task::yield_now().await;
+        format!("{}, a square", self.name)
+    }
+}
+
+impl Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}x{})", self.name, self.side, self.side)
+    }
+}
+
+impl Shape for Square {
+    fn area(&self) -> f64 {
+        self.side * self.side
+    }
+
+    fn grow(&mut self, factor: f64) {
+        self.side *= factor;
+    }
+}
+
+fn square(name: &str, side: f64) -> Square {
+    Square {
+        name: name.to_string(),
+        side,
+    }
+}
+
+fn names(items: &[impl Named]) -> Vec<String> {
+    items.iter().map(|item| item.name()).collect()
+}
+
+#[tokio::test]
+async fn dyn_shape_is_named() {
+    let mut shapes = vec![
+        DynShape::new(square("a", 1.0)),
+        DynShape::new(square("b", 2.0)),
+    ];
+    shapes[1].rename("c");
+    assert_eq!(names(&shapes), vec!["a", "c"]);
+    assert_eq!(shapes[1].describe().await, "c, a square");
+}
+
+#[test]
+fn dyn_shape_fmt() {
+    let mut shape = square("a", 2.0);
+    let mut dyn_shape = DynShape::from_ptr(&mut shape);
+    dyn_shape.grow(1.5);
+    assert_eq!(dyn_shape.area(), 9.0);
+    assert_eq!(dyn_shape.to_string(), "a (3x3)");
+    assert_eq!(
+        format!("{:?}", *dyn_shape),
+        format!("{:?}", square("a", 3.0))
+    );
+}
+
+#[test]
+fn as_named() {
+    let shape = DynShape::new(square("a", 1.0));
+    let named: &DynNamed<'_> = &shape.as_named();
+    assert_eq!(named.name(), "a");
+}
+
+/// The upcast shares our pointer, so dropping it mustn't drop the shape.
+#[tokio::test]
+async fn as_named_then_use() {
+    let mut shape = DynShape::new(square("a", 1.0));
+    assert_eq!(shape.as_named().describe().await, "a, a square");
+    shape.rename("b");
+    assert_eq!(shape.as_named().name(), "b");
+
+    let rc = std::rc::Rc::new(square("c", 1.0));
+    let shared = DynShape::from_ptr(rc.clone());
+    assert_eq!(shared.as_named().name(), "c");
+    drop(shared);
+    assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+}
+
+#[test]
+fn into_named_owned() {
+    let named: DynNamed<'static> = DynShape::new(square("a", 1.0)).into_named();
+    assert_eq!(named.into_name(), "a");
+}
+
+#[test]
+#[should_panic(expected = "cannot call a `self` method on a borrowed object")]
+fn into_named_borrowed() {
+    let mut borrowed = square("a", 1.0);
+    let mut owned = DynShape::new(square("b", 1.0));
+    std::mem::swap(&mut *DynShape::from_ptr(&mut borrowed), &mut owned);
+    owned.into_named().into_name();
+}
+
+/// Boxed in an arena, which a `self` method and drop must free it through:
+/// the global allocator would reject the arena's memory.
+#[test]
+fn from_box_in() {
+    let arena = Bump::<256>::new();
+    let named = DynNamed::from_ptr(Box::new_in(square("a", 1.0), &arena));
+    assert_eq!(named.into_name(), "a");
+    let shape = DynShape::from_ptr(Box::new_in(square("b", 2.0), &arena));
+    assert_eq!(shape.area(), 4.0);
+    drop(shape);
+    assert!(arena.used() >= 2 * std::mem::size_of::<Square>());
+}
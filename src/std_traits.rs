@@ -0,0 +1,136 @@
+//! Forwarding `Debug`, `Display` and `Clone` with `#[dyner(..)]`.
+
+use ::dyner::dyner::Ref;
+use dyner::dyner;
+use std::cell::Cell;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+#[dyner(Debug, Clone)]
+pub trait Counter {
+    fn incr(&mut self);
+    fn get(&self) -> u32;
+}
+
+#[derive(Clone, Debug, Default)]
+struct Plain {
+    count: u32,
+}
+
+impl Counter for Plain {
+    fn incr(&mut self) {
+        self.count += 1;
+    }
+
+    fn get(&self) -> u32 {
+        self.count
+    }
+}
+
+/// Counts in a cell, so that we can see changes through a shared reference.
+#[derive(Clone, Debug, Default)]
+struct Shared {
+    count: Cell<u32>,
+}
+
+impl Counter for Shared {
+    fn incr(&mut self) {
+        self.count.set(self.count.get() + 1);
+    }
+
+    fn get(&self) -> u32 {
+        self.count.get()
+    }
+}
+
+/// `Clone` can also be a supertrait, and associated types still work.
+#[dyner(Display)]
+pub trait Source: Clone {
+    type Item;
+
+    fn next_item(&mut self) -> Option<Self::Item>;
+}
+
+#[derive(Clone)]
+struct Countdown(u32);
+
+impl Source for Countdown {
+    type Item = u32;
+
+    fn next_item(&mut self) -> Option<u32> {
+        self.0 = self.0.checked_sub(1)?;
+        Some(self.0)
+    }
+}
+
+impl Display for Countdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}...", self.0)
+    }
+}
+
+#[test]
+fn clone_owned() {
+    let mut counter = DynCounter::new(Plain::default());
+    counter.incr();
+    let mut clone = counter.clone();
+    clone.incr();
+    assert_eq!((counter.get(), clone.get()), (1, 2));
+}
+
+#[test]
+fn clone_ref_copies_reference() {
+    let shared = Shared::default();
+    let counter = DynCounter::from_ptr(&shared);
+    let clone: Ref<DynCounter<'_>> = counter.clone();
+    shared.count.set(5);
+    assert_eq!((counter.get(), clone.get()), (5, 5));
+}
+
+#[test]
+fn clone_through_ref_boxes() {
+    let shared = Shared::default();
+    let counter = DynCounter::from_ptr(&shared);
+    let mut clone: DynCounter<'_> = (*counter).clone();
+    clone.incr();
+    assert_eq!((counter.get(), clone.get()), (0, 1));
+}
+
+#[test]
+fn clone_rc_shares() {
+    let plain = Rc::new(Plain::default());
+    let counter = DynCounter::from_ptr(plain.clone());
+    let clone = counter.clone();
+    assert_eq!(Rc::strong_count(&plain), 3);
+    drop((counter, clone));
+    assert_eq!(Rc::strong_count(&plain), 1);
+}
+
+#[test]
+fn clone_ref_mut_boxes() {
+    let mut plain = Plain::default();
+    let mut counter = DynCounter::from_ptr(&mut plain);
+    counter.incr();
+    let mut clone: DynCounter<'_> = counter.clone();
+    clone.incr();
+    assert_eq!((counter.get(), clone.get()), (1, 2));
+}
+
+#[test]
+fn debug() {
+    let counter = DynCounter::new(Plain { count: 3 });
+    assert_eq!(format!("{counter:?}"), "Plain { count: 3 }");
+}
+
+#[test]
+fn clone_supertrait() {
+    let mut source = DynSource::new(Countdown(2));
+    assert_eq!(source.next_item(), Some(1));
+    let mut clone = source.clone();
+    assert_eq!(clone.next_item(), Some(0));
+    assert_eq!(clone.next_item(), None);
+    assert_eq!(
+        (source.to_string(), clone.to_string()),
+        ("1...".into(), "0...".into())
+    );
+}
@@ -0,0 +1,211 @@
+//! Erasing generic associated types (borrowed views, lending iterators)
+//! with `#[dyner]`.
+
+use dyner::dyner;
+use std::fmt::{self, Debug, Display};
+use std::future::{self, Future, Ready};
+use std::slice;
+
+#[dyner]
+pub trait Cursor {
+    fn read_byte(&mut self) -> Option<u8>;
+}
+
+impl Cursor for slice::Iter<'_, u8> {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.next().copied()
+    }
+}
+
+#[dyner]
+pub trait Buffer {
+    type Bytes<'a>: Iterator<Item = &'a u8> + 'a
+    where
+        Self: 'a;
+
+    type View<'a>: Display + 'a
+    where
+        Self: 'a;
+
+    #[dyner]
+    type Reader<'a>: Cursor + 'a
+    where
+        Self: 'a;
+
+    type Len<'a>: Future<Output = usize> + 'a
+    where
+        Self: 'a;
+
+    fn bytes(&self) -> Self::Bytes<'_>;
+    fn view(&self) -> Self::View<'_>;
+    fn reader(&self) -> Self::Reader<'_>;
+    fn len(&self) -> Self::Len<'_>;
+}
+
+/// Displays bytes in hex.
+struct Hex<'a>(&'a [u8]);
+
+impl Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+struct VecBuffer(Vec<u8>);
+
+impl Buffer for VecBuffer {
+    type Bytes<'a> = slice::Iter<'a, u8>;
+
+    type View<'a> = Hex<'a>;
+
+    type Reader<'a> = slice::Iter<'a, u8>;
+
+    type Len<'a> = Ready<usize>;
+
+    fn bytes(&self) -> slice::Iter<'_, u8> {
+        self.0.iter()
+    }
+
+    fn view(&self) -> Hex<'_> {
+        Hex(&self.0)
+    }
+
+    fn reader(&self) -> slice::Iter<'_, u8> {
+        self.0.iter()
+    }
+
+    fn len(&self) -> Ready<usize> {
+        future::ready(self.0.len())
+    }
+}
+
+/// A lending iterator over the windows of a buffer.
+#[dyner]
+pub trait Windows {
+    type Window<'a>: Display + 'a
+    where
+        Self: 'a;
+
+    fn next_window(&mut self) -> Option<Self::Window<'_>>;
+}
+
+struct ByteWindows {
+    bytes: Vec<u8>,
+    start: usize,
+    size: usize,
+}
+
+impl Windows for ByteWindows {
+    type Window<'a> = Hex<'a>;
+
+    fn next_window(&mut self) -> Option<Hex<'_>> {
+        let window = self.bytes.get(self.start..self.start + self.size)?;
+        self.start += 1;
+        Some(Hex(window))
+    }
+}
+
+/// Also names `VecBuffer`'s `View`, which shares a name with ours but
+/// isn't ours to erase.
+#[dyner]
+trait Preview {
+    type View<'a>: Display + 'a
+    where
+        Self: 'a;
+
+    fn preview(&self) -> Self::View<'_>;
+    fn buffer_view<'b>(&self, buffer: &'b VecBuffer) -> <VecBuffer as Buffer>::View<'b>;
+}
+
+impl Preview for VecBuffer {
+    type View<'a> = Hex<'a>;
+
+    fn preview(&self) -> Hex<'_> {
+        Hex(&self.0[..1])
+    }
+
+    fn buffer_view<'b>(&self, buffer: &'b VecBuffer) -> Hex<'b> {
+        buffer.view()
+    }
+}
+
+/// Has a `View` of its own, as well as `Buffer`'s, so it has to name it as
+/// `<Self as Framed>::View`.
+#[dyner]
+pub trait Framed: Buffer {
+    type View<'a>: Debug + 'a
+    where
+        Self: 'a;
+
+    fn frame(&self) -> <Self as Framed>::View<'_>;
+}
+
+impl Framed for VecBuffer {
+    type View<'a> = &'a [u8];
+
+    fn frame(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+fn all_windows(windows: &mut impl Windows) -> Vec<String> {
+    let mut all = vec![];
+    while let Some(window) = windows.next_window() {
+        all.push(window.to_string());
+    }
+    all
+}
+
+#[tokio::test]
+async fn dyn_buffer_new() {
+    let buffer = DynBuffer::new(VecBuffer(vec![1, 2, 0xff]));
+    assert_eq!(
+        buffer.bytes().copied().collect::<Vec<_>>(),
+        vec![1, 2, 0xff]
+    );
+    assert_eq!(buffer.view().to_string(), "0102ff");
+    assert_eq!(buffer.len().await, 3);
+
+    let mut reader: DynCursor<'_> = buffer.reader();
+    assert_eq!(reader.read_byte(), Some(1));
+    assert_eq!(reader.read_byte(), Some(2));
+}
+
+#[test]
+fn dyn_buffer_from_ref() {
+    let buffer = VecBuffer(vec![0xab]);
+    let view = DynBuffer::from_ptr(&buffer).view().to_string();
+    assert_eq!(view, "ab");
+}
+
+#[test]
+fn dyn_windows_lending() {
+    let mut windows = ByteWindows {
+        bytes: vec![1, 2, 3],
+        start: 0,
+        size: 2,
+    };
+    assert_eq!(
+        all_windows(&mut *DynWindows::from_ptr(&mut windows)),
+        vec!["0102", "0203"]
+    );
+    assert_eq!(windows.start, 2);
+}
+
+#[test]
+fn dyn_preview_other_view() {
+    let buffer = VecBuffer(vec![1, 2]);
+    let preview = DynPreview::new(VecBuffer(vec![3, 4]));
+    let view: Hex<'_> = preview.buffer_view(&buffer);
+    assert_eq!(
+        (preview.preview().to_string(), view.to_string()),
+        ("03".into(), "0102".into())
+    );
+}
+
+#[test]
+fn dyn_framed_supertrait_view() {
+    let framed = DynFramed::new(VecBuffer(vec![1, 0xff]));
+    assert_eq!(format!("{:?}", framed.frame()), "[1, 255]");
+    assert_eq!(Buffer::view(&framed).to_string(), "01ff");
+}
@@ -0,0 +1,119 @@
+//! Erasing generic methods with `#[dyner]`.
+
+use dyner::dyner;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tokio::task;
+
+#[dyner]
+pub trait Visitor {
+    fn visit_num(&mut self, n: u32);
+    fn visit_word(&mut self, word: &str);
+}
+
+#[dyner]
+pub trait Node {
+    fn accept<V: Visitor>(&self, visitor: &mut V);
+    fn accept_owned<V>(&self, visitor: V)
+    where
+        V: Visitor;
+    async fn accept_async<V: Visitor + ?Sized>(&self, visitor: &mut V);
+}
+
+/// Records what it visits.
+#[derive(Clone, Default)]
+struct Recorder {
+    visited: Rc<RefCell<Vec<String>>>,
+}
+
+impl Recorder {
+    fn visited(&self) -> Vec<String> {
+        self.visited.borrow().clone()
+    }
+}
+
+impl Visitor for Recorder {
+    fn visit_num(&mut self, n: u32) {
+        self.visited.borrow_mut().push(n.to_string());
+    }
+
+    fn visit_word(&mut self, word: &str) {
+        self.visited.borrow_mut().push(word.to_string());
+    }
+}
+
+struct Num(u32);
+
+impl Node for Num {
+    fn accept<V: Visitor>(&self, visitor: &mut V) {
+        visitor.visit_num(self.0);
+    }
+
+    fn accept_owned<V>(&self, mut visitor: V)
+    where
+        V: Visitor,
+    {
+        visitor.visit_num(self.0);
+    }
+
+    async fn accept_async<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+        task::yield_now().await;
+        visitor.visit_num(self.0);
+    }
+}
+
+/// Passes its visitor on to each of its (erased) children.
+struct List(Vec<DynNode<'static>>);
+
+impl Node for List {
+    fn accept<V: Visitor>(&self, visitor: &mut V) {
+        visitor.visit_word("[");
+        for node in &self.0 {
+            node.accept(visitor);
+        }
+        visitor.visit_word("]");
+    }
+
+    fn accept_owned<V>(&self, mut visitor: V)
+    where
+        V: Visitor,
+    {
+        self.accept(&mut visitor);
+    }
+
+    async fn accept_async<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+        for node in &self.0 {
+            node.accept_async(visitor).await;
+        }
+    }
+}
+
+fn tree() -> DynNode<'static> {
+    DynNode::new(List(vec![
+        DynNode::new(Num(1)),
+        DynNode::new(List(vec![DynNode::new(Num(2))])),
+    ]))
+}
+
+#[test]
+fn accept_ref_mut() {
+    let mut recorder = Recorder::default();
+    tree().accept(&mut recorder);
+    assert_eq!(recorder.visited(), vec!["[", "1", "[", "2", "]", "]"]);
+}
+
+#[test]
+fn accept_owned() {
+    let recorder = Recorder::default();
+    DynNode::from_ptr(&Num(22)).accept_owned(recorder.clone());
+    tree().accept_owned(recorder.clone());
+    assert_eq!(recorder.visited(), vec!["22", "[", "1", "[", "2", "]", "]"]);
+}
+
+#[tokio::test]
+async fn accept_async_dyn_visitor() {
+    let recorder = Recorder::default();
+    let mut visitor = DynVisitor::new(recorder.clone());
+    tree().accept_async(&mut visitor).await;
+    assert_eq!(recorder.visited(), vec!["1", "2"]);
+}